@@ -0,0 +1,413 @@
+use crate::parameters::*;
+use crate::synth_device::*;
+use crate::sample_player::*;
+use crate::sample_loader;
+use crate::state_variable_filter::*;
+use crate::envelope::*;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+use std::sync::Arc;
+
+// The note that plays the sample at its original pitch.
+const ROOT_NOTE : f64 = 60.0;
+
+pub struct AdulteryParams {
+    dev_params:             SynthDeviceParams,
+    params:                 SignalIOParams,
+    sample:                 Arc<Vec<f32>>,
+    sample_loop_start:      i32,
+    sample_loop_length:     i32,
+    root_note:              f64,
+    sample_rate:            f64,
+    sample_start:           f32,
+    reverse:                bool,
+    loop_mode:              LoopMode,
+    loop_boundary_mode:     LoopBoundaryMode,
+    loop_start:             f32,
+    loop_length:            f32,
+    loop_crossfade:         f32,
+    interpolation_mode:     InterpolationMode,
+    // Incremented when a player parameter changes, voices only
+    // update their player when it differs from theirs.
+    player_gen:             u64,
+    coarse_tune:            f32,
+    fine_tune:              f32,
+    amp_attack:             f32,
+    amp_decay:              f32,
+    amp_sustain:            f32,
+    amp_release:            f32,
+    filter_type:            FilterType,
+    filter_freq:            f32,
+    filter_resonance:       f32,
+    filter_mod_amt:         f32,
+    mod_attack:             f32,
+    mod_decay:              f32,
+    mod_sustain:            f32,
+    mod_release:            f32,
+    vel_amp:                f32,
+}
+
+impl AdulteryParams {
+    pub fn new() -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("smp_st",     0.0, 1.0, 0.0);
+        p.input("rev",        0.0, 1.0, 0.0);
+        p.input("lp_mode",    0.0, 1.0, 0.0);
+        p.input("lp_bnd",     0.0, 1.0, 0.0);
+        p.input("lp_st",      0.0, 1.0, 0.0);
+        p.input("lp_len",     0.0, 1.0, 1.0);
+        p.input("interp",     0.0, 1.0, InterpolationMode::Linear.into());
+        p.input("tune_c",     0.0, 1.0, 0.5);
+        p.input("tune_f",     0.0, 1.0, 0.5);
+        p.input("amp_a",      0.0, 1.0, 1.0);
+        p.input("amp_d",      0.0, 1.0, 5.0);
+        p.input("amp_s",      0.0, 1.0, 1.0);
+        p.input("amp_r",      0.0, 1.0, 1.5);
+        p.input("f_typ",      0.0, 1.0, 0.0);
+        p.input("f_freq",     0.0, 1.0, 1.0);
+        p.input("f_res",      0.0, 1.0, 0.0);
+        p.input("f_mod",      0.0, 1.0, 0.5);
+        p.input("mod_a",      0.0, 1.0, 1.0);
+        p.input("mod_d",      0.0, 1.0, 5.0);
+        p.input("mod_s",      0.0, 1.0, 1.0);
+        p.input("mod_r",      0.0, 1.0, 1.5);
+        p.input("vel_amp",    0.0, 1.0, 1.0);
+        p.input("lp_xf",      0.0, 1.0, 0.0);
+
+        let dev_params = SynthDeviceParams::new_with_params(&mut p);
+
+        AdulteryParams {
+            dev_params,
+            sample:             Arc::new(Vec::new()),
+            sample_loop_start:  0,
+            sample_loop_length: 0,
+            root_note:          ROOT_NOTE,
+            sample_rate:        0.0,
+            player_gen:         0,
+            sample_start:       p.v(0),
+            reverse:            helpers::param_to_boolean(p.v(1)),
+            loop_mode:          p.v(2).into(),
+            loop_boundary_mode: p.v(3).into(),
+            loop_start:         p.v(4),
+            loop_length:        p.v(5),
+            loop_crossfade:     p.v(22),
+            interpolation_mode: p.v(6).into(),
+            coarse_tune:        0.0,
+            fine_tune:          0.0,
+            amp_attack:         p.v(9),
+            amp_decay:          p.v(10),
+            amp_sustain:        p.v(11),
+            amp_release:        p.v(12),
+            filter_type:        p.v(13).into(),
+            filter_freq:        helpers::param_to_frequency(p.v(14)),
+            filter_resonance:   1.0 - p.v(15),
+            filter_mod_amt:     p.v(16),
+            mod_attack:         p.v(17),
+            mod_decay:          p.v(18),
+            mod_sustain:        p.v(19),
+            mod_release:        p.v(20),
+            vel_amp:            p.v(21),
+
+            params:             p,
+        }
+    }
+
+    /// Sets the sample data and the loop boundaries that are used
+    /// with `LoopBoundaryMode::FromSample`.
+    pub fn set_sample(&mut self, data: Vec<f32>, loop_start: i32, loop_length: i32) {
+        self.sample             = Arc::new(data);
+        self.sample_loop_start  = loop_start;
+        self.sample_loop_length = loop_length;
+        self.sample_rate        = 0.0;
+    }
+
+    /// Loads a WAV file and takes the loop boundaries and the
+    /// root note from its `smpl`/`inst` chunks, if present.
+    pub fn load_sample(&mut self, file: &str) -> Result<(), sample_loader::LoadError> {
+        let sample = sample_loader::load_wav(file)?;
+        let len    = sample.len() as i32;
+
+        self.root_note = sample.root_note as f64 + sample.fine_tune as f64 / 100.0;
+        match sample.sample_loop {
+            Some(l) => self.set_sample(sample.to_mono(), l.start, l.length),
+            None    => self.set_sample(sample.to_mono(), 0, len),
+        }
+        self.sample_rate = sample.sample_rate as f64;
+        Ok(())
+    }
+
+    fn player_params(&self) -> (f32, bool, LoopMode, LoopBoundaryMode, f32, f32, f32, InterpolationMode) {
+        (self.sample_start, self.reverse, self.loop_mode, self.loop_boundary_mode,
+         self.loop_start, self.loop_length, self.loop_crossfade, self.interpolation_mode)
+    }
+}
+
+impl AsMut<SignalIOParams> for AdulteryParams {
+    fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+}
+
+impl VoiceParams for AdulteryParams {
+    fn update(&mut self) -> SynthDeviceParams {
+        let player_params = self.player_params();
+        let v             = &self.params.values;
+
+        self.sample_start       = v[0];
+        self.reverse            = helpers::param_to_boolean(v[1]);
+        self.loop_mode          = v[2].into();
+        self.loop_boundary_mode = v[3].into();
+        self.loop_start         = v[4];
+        self.loop_length        = v[5];
+        self.interpolation_mode = v[6].into();
+        self.coarse_tune        = ((v[7] - 0.5) * 2.0 * 36.0).floor();
+        self.fine_tune          = (v[8] - 0.5) * 2.0;
+        self.amp_attack         = helpers::scalar_to_env_value(v[9]);
+        self.amp_decay          = helpers::scalar_to_env_value(v[10]);
+        self.amp_sustain        = v[11];
+        self.amp_release        = helpers::scalar_to_env_value(v[12]);
+        self.filter_type        = v[13].into();
+        self.filter_freq        = helpers::param_to_frequency(v[14]);
+        self.filter_resonance   = 1.0 - v[15];
+        self.filter_mod_amt     = v[16];
+        self.mod_attack         = helpers::scalar_to_env_value(v[17]);
+        self.mod_decay          = helpers::scalar_to_env_value(v[18]);
+        self.mod_sustain        = v[19];
+        self.mod_release        = helpers::scalar_to_env_value(v[20]);
+        self.vel_amp            = v[21];
+        self.loop_crossfade     = v[22];
+
+        if self.player_params() != player_params {
+            self.player_gen += 1;
+        }
+
+        self.dev_params.update(&self.params);
+        self.dev_params
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AdulteryVoice {
+    player:     SamplePlayer,
+    filter:     Filter,
+    amp_env:    Envelope,
+    mod_env:    Envelope,
+    last_note:  f64,
+    player_gen: u64,
+}
+
+impl AdulteryVoice {
+    fn update_player(&mut self, params: &AdulteryParams) {
+        self.player.sample_start       = params.sample_start;
+        self.player.reverse            = params.reverse;
+        self.player.loop_mode          = params.loop_mode;
+        self.player.loop_boundary_mode = params.loop_boundary_mode;
+        self.player.loop_start         = params.loop_start;
+        self.player.loop_length        = params.loop_length;
+        self.player.loop_crossfade     = params.loop_crossfade;
+        self.player.interpolation_mode = params.interpolation_mode;
+        self.player.sample_loop_start  = params.sample_loop_start;
+        self.player.sample_loop_length = params.sample_loop_length;
+        self.player.set_source_sample_rate(params.sample_rate);
+        self.player.run_prep();
+        self.player_gen = params.player_gen;
+    }
+}
+
+impl Voice<AdulteryParams> for AdulteryVoice {
+    fn new(sample_rate: f64) -> Self {
+        AdulteryVoice {
+            player:     SamplePlayer::new(sample_rate),
+            filter:     Filter::new(sample_rate),
+            amp_env:    Envelope::new(sample_rate),
+            mod_env:    Envelope::new(sample_rate),
+            last_note:  -1.0,
+            player_gen: 0,
+        }
+    }
+    fn note_on(&mut self, data: &mut VoiceData, params: &mut AdulteryParams, note: i32, velocity: i32, detune: f32, pan: f32) {
+        data.note_on(note, velocity, detune, pan);
+
+        self.amp_env.attack     = params.amp_attack;
+        self.amp_env.decay      = params.amp_decay;
+        self.amp_env.sustain    = params.amp_sustain;
+        self.amp_env.release    = params.amp_release;
+        self.amp_env.trigger();
+
+        self.mod_env.attack     = params.mod_attack;
+        self.mod_env.decay      = params.mod_decay;
+        self.mod_env.sustain    = params.mod_sustain;
+        self.mod_env.release    = params.mod_release;
+        self.mod_env.trigger();
+
+        self.player.sample_data = params.sample.clone();
+        self.update_player(params);
+
+        if self.player.sample_data.is_empty() {
+            self.player.is_active = false;
+        } else {
+            self.player.init_pos();
+        }
+        self.last_note = -1.0;
+    }
+    fn note_off(&mut self, data: &mut VoiceData, _params: &mut AdulteryParams) {
+        data.note_off();
+        self.amp_env.off();
+        self.mod_env.off();
+    }
+    fn note_slide(&mut self, data: &mut VoiceData, _params: &mut AdulteryParams, slide: f32, note: i32) {
+        data.note_slide(slide, note);
+    }
+    fn get_note(&mut self, data: &mut VoiceData, _params: &mut AdulteryParams) -> f64 {
+        data.get_note()
+    }
+    fn is_finished(&self, _data: &VoiceData, _params: &AdulteryParams) -> bool {
+        self.amp_env.state == EnvelopeState::Finished
+        || !self.player.is_active
+    }
+    fn level(&self, _data: &VoiceData, _params: &AdulteryParams) -> f32 {
+        if self.player.is_active { self.amp_env.get_value() } else { 0.0 }
+    }
+    fn run(&mut self,
+           data: &mut VoiceData,
+           params: &mut AdulteryParams,
+           _song_pos: f64,
+           sample_num: usize,
+           out_offs: usize,
+           outputs: &mut [f32]) {
+
+        if !self.player.is_active {
+            // The sample has ended, is_finished() lets the
+            // SynthDevice free the voice.
+            return;
+        }
+
+        if self.player_gen != params.player_gen {
+            self.update_player(params);
+        }
+
+        self.filter.set_type(params.filter_type);
+        self.filter.set_q(params.filter_resonance);
+
+        let amp       = helpers::volume_to_scalar(data.master_level)
+                        * (1.0 - params.vel_amp * (1.0 - data.velocity));
+        let pan_left  = helpers::pan_to_scalar_left(data.pan);
+        let pan_right = helpers::pan_to_scalar_right(data.pan);
+
+        let tune       = (params.coarse_tune + params.fine_tune) as f64;
+        let filter_mod = (20000.0 - 20.0) * (params.filter_mod_amt * 2.0 - 1.0);
+
+        for i in 0..sample_num {
+            let note = data.get_note() - params.root_note + tune;
+            if note != self.last_note {
+                self.player.calc_pitch(note);
+                self.last_note = note;
+            }
+
+            self.filter.set_freq(
+                helpers::clamp(
+                    params.filter_freq
+                    + self.mod_env.get_value() * filter_mod,
+                    0.0, 20000.0 - 20.0));
+
+            let s =
+                self.filter.next(self.player.next())
+                * self.amp_env.get_value() * amp;
+            outputs[(out_offs + i) * 2]     += s * pan_left;
+            outputs[(out_offs + i) * 2 + 1] += s * pan_right;
+
+            self.amp_env.next();
+            self.mod_env.next();
+
+            if !self.player.is_active {
+                break;
+            }
+        }
+    }
+}
+
+impl Op for SynthDevice<AdulteryVoice, AdulteryParams> {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.params.ports.clone(),
+            input_values:     self.params.params.inputs.clone(),
+            input_defaults:   self.params.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, ev: &Event) {
+        self.op_event(ev);
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.exec_params(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let mut f : [f32; 1] = [0.0; 1];
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.run(0.0, num_samples, &mut f, buf);
+    }
+}
+
+pub fn new_adultery(sample_rate: f64) -> SynthDevice<AdulteryVoice, AdulteryParams> {
+    let params     = AdulteryParams::new();
+    let dev_params = params.dev_params;
+    let mut sd : SynthDevice<AdulteryVoice, AdulteryParams> =
+        SynthDevice::new(sample_rate, params);
+    sd.set_dev_params(&dev_params);
+    sd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_voice(v: &mut AdulteryVoice, data: &mut VoiceData,
+                 params: &mut AdulteryParams, len: usize) {
+        let mut out = vec![0.0; len * 2];
+        v.run(data, params, 0.0, len, 0, &mut out);
+    }
+
+    #[test]
+    fn test_player_only_updated_on_change() {
+        let mut params = AdulteryParams::new();
+        params.set_sample(vec![0.5; 1000], 0, 1000);
+
+        let mut data = VoiceData::new(44100.0);
+        let mut v    = AdulteryVoice::new(44100.0);
+        v.note_on(&mut data, &mut params, 60, 127, 0.0, 0.5);
+        run_voice(&mut v, &mut data, &mut params, 64);
+
+        // Unchanged port values keep the generation:
+        let gen = params.player_gen;
+        params.update();
+        assert_eq!(params.player_gen, gen);
+
+        v.player.loop_start = 0.25;
+        run_voice(&mut v, &mut data, &mut params, 64);
+        assert_eq!(v.player.loop_start, 0.25);
+
+        // A changed player parameter prepares the player again:
+        params.params.values[4] = 0.5;
+        params.update();
+        assert_eq!(params.player_gen, gen + 1);
+
+        run_voice(&mut v, &mut data, &mut params, 64);
+        assert_eq!(v.player.loop_start, 0.5);
+        assert_eq!(v.player_gen, params.player_gen);
+    }
+}
@@ -0,0 +1,162 @@
+use crate::parameters::*;
+
+/// A biquad filter with the coefficient formulas from Robert
+/// Bristow-Johnson's "Audio EQ Cookbook". Processes a single channel.
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    sample_rate: f64,
+    recalculate: bool,
+    filter_type: BiquadType,
+    freq:        f32,
+    q:           f32,
+    gain:        f32,
+    b0:          f64,
+    b1:          f64,
+    b2:          f64,
+    a1:          f64,
+    a2:          f64,
+    x1:          f64,
+    x2:          f64,
+    y1:          f64,
+    y2:          f64,
+}
+
+impl Biquad {
+    pub fn new(sample_rate: f64) -> Self {
+        Biquad {
+            sample_rate,
+            recalculate: true,
+            filter_type: BiquadType::Peak,
+            freq:        1000.0,
+            q:           1.0,
+            gain:        0.0,
+            b0:          1.0,
+            b1:          0.0,
+            b2:          0.0,
+            a1:          0.0,
+            a2:          0.0,
+            x1:          0.0,
+            x2:          0.0,
+            y1:          0.0,
+            y2:          0.0,
+        }
+    }
+
+    recalc_setter!(set_type,    filter_type, BiquadType);
+    recalc_setter!(set_freq,    freq,        f32);
+    recalc_setter!(set_q,       q,           f32);
+    recalc_setter!(set_gain,    gain,        f32);
+
+    fn calc_coefs(&mut self) {
+        let freq  = (self.freq as f64).max(1.0).min(self.sample_rate * 0.49);
+        let w0    = 2.0 * std::f64::consts::PI * freq / self.sample_rate;
+        let cos   = w0.cos();
+        let alpha = w0.sin() / (2.0 * (self.q as f64).max(0.01));
+        let a     = 10.0_f64.powf(self.gain as f64 / 40.0);
+
+        let (b0, b1, b2, a0, a1, a2) =
+            match self.filter_type {
+                BiquadType::Lowpass => (
+                    (1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0,
+                    1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+                BiquadType::Highpass => (
+                    (1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0,
+                    1.0 + alpha, -2.0 * cos, 1.0 - alpha),
+                BiquadType::Peak => (
+                    1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a,
+                    1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a),
+                BiquadType::LowShelf => {
+                    let sq = 2.0 * a.sqrt() * alpha;
+                    (a * ((a + 1.0) - (a - 1.0) * cos + sq),
+                     2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                     a * ((a + 1.0) - (a - 1.0) * cos - sq),
+                     (a + 1.0) + (a - 1.0) * cos + sq,
+                     -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                     (a + 1.0) + (a - 1.0) * cos - sq)
+                },
+                BiquadType::HighShelf => {
+                    let sq = 2.0 * a.sqrt() * alpha;
+                    (a * ((a + 1.0) + (a - 1.0) * cos + sq),
+                     -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                     a * ((a + 1.0) + (a - 1.0) * cos - sq),
+                     (a + 1.0) - (a - 1.0) * cos + sq,
+                     2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                     (a + 1.0) - (a - 1.0) * cos - sq)
+                },
+            };
+
+        self.b0 = b0 / a0;
+        self.b1 = b1 / a0;
+        self.b2 = b2 / a0;
+        self.a1 = a1 / a0;
+        self.a2 = a2 / a0;
+    }
+
+    pub fn reset(&mut self) {
+        self.x1 = 0.0;
+        self.x2 = 0.0;
+        self.y1 = 0.0;
+        self.y2 = 0.0;
+    }
+
+    pub fn next(&mut self, input: f32) -> f32 {
+        if self.recalculate {
+            self.calc_coefs();
+            self.recalculate = false;
+        }
+
+        let x = input as f64;
+        let y =
+            self.b0 * x + self.b1 * self.x1 + self.b2 * self.x2
+            - self.a1 * self.y1 - self.a2 * self.y2;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+
+        y as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine_gain(bq: &mut Biquad, freq: f64) -> f32 {
+        let mut peak : f32 = 0.0;
+        for i in 0..44100 {
+            let x =
+                (2.0 * std::f64::consts::PI * freq * i as f64 / 44100.0).sin();
+            let y = bq.next(x as f32);
+            if i > 22050 { peak = peak.max(y.abs()); }
+        }
+        peak
+    }
+
+    #[test]
+    fn test_biquad_peak_gain() {
+        let mut bq = Biquad::new(44100.0);
+        bq.set_type(BiquadType::Peak);
+        bq.set_freq(1000.0);
+        bq.set_q(1.0);
+        bq.set_gain(6.0);
+        let g = sine_gain(&mut bq, 1000.0);
+        assert!((g - 1.995).abs() < 0.01, "gain at center: {}", g);
+
+        bq.reset();
+        let g = sine_gain(&mut bq, 50.0);
+        assert!((g - 1.0).abs() < 0.02, "gain far below center: {}", g);
+    }
+
+    #[test]
+    fn test_biquad_lowpass() {
+        let mut bq = Biquad::new(44100.0);
+        bq.set_type(BiquadType::Lowpass);
+        bq.set_freq(500.0);
+        bq.set_q(0.707);
+        assert!((sine_gain(&mut bq, 100.0) - 1.0).abs() < 0.02);
+        bq.reset();
+        assert!(sine_gain(&mut bq, 5000.0) < 0.02);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::all_pass::AllPass;
+use crate::comb::Comb;
+use crate::delay_buffer::DelayBuffer;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Buffer lengths and scalings of the Freeverb algorithm by
+// Jezar at Dreampoint, which are given for 44.1kHz.
+const COMB_TUNING     : [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALL_PASS_TUNING : [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD   : usize      = 23;
+const FIXED_GAIN      : f32        = 0.015;
+const SCALE_DAMP      : f32        = 0.4;
+const SCALE_ROOM      : f32        = 0.28;
+const OFFSET_ROOM     : f32        = 0.7;
+const SCALE_WET       : f32        = 3.0;
+// Longest pre-delay in seconds.
+const MAX_PRE_DELAY   : f64        = 0.5;
+
+/// A stereo Freeverb style reverb: parallel lowpass feedback combs
+/// followed by all-passes in series for each channel. The right
+/// channel uses slightly longer buffers for the stereo spread.
+pub struct Cathedral {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    room_size:      f32,
+    damp:           f32,
+    width:          f32,
+    pre_delay:      f32,
+    freeze:         bool,
+    dry:            f32,
+    wet:            f32,
+    combs_l:        Vec<Comb>,
+    combs_r:        Vec<Comb>,
+    all_passes_l:   Vec<AllPass>,
+    all_passes_r:   Vec<AllPass>,
+    pre_delay_l:    DelayBuffer,
+    pre_delay_r:    DelayBuffer,
+}
+
+impl Cathedral {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("room",       0.0, 1.0, 0.5);
+        p.input("damp",       0.0, 1.0, 0.5);
+        p.input("width",      0.0, 1.0, 1.0);
+        p.input("pre_dly",    0.0, 1.0, 0.0);
+        p.input("freeze",     0.0, 1.0, 0.0);
+        p.input("dry",        0.0, 1.0, 1.0);
+        p.input("wet",        0.0, 1.0, 0.3);
+
+        let scale = sample_rate / 44100.0;
+        let tuned = |len: usize| ((len as f64 * scale) as usize).max(1);
+
+        let mut combs_l      = vec![];
+        let mut combs_r      = vec![];
+        for len in COMB_TUNING.iter() {
+            let mut c = Comb::new();
+            c.set_buffer_size(tuned(*len));
+            combs_l.push(c);
+
+            let mut c = Comb::new();
+            c.set_buffer_size(tuned(*len + STEREO_SPREAD));
+            combs_r.push(c);
+        }
+
+        let mut all_passes_l = vec![];
+        let mut all_passes_r = vec![];
+        for len in ALL_PASS_TUNING.iter() {
+            let mut a = AllPass::new();
+            a.set_buffer_size(tuned(*len));
+            a.set_feedback(0.5);
+            all_passes_l.push(a);
+
+            let mut a = AllPass::new();
+            a.set_buffer_size(tuned(*len + STEREO_SPREAD));
+            a.set_feedback(0.5);
+            all_passes_r.push(a);
+        }
+
+        let pre_delay_len = (MAX_PRE_DELAY * sample_rate) as usize + 1;
+
+        Cathedral {
+            sample_rate,
+            room_size:      p.v(0),
+            damp:           p.v(1),
+            width:          p.v(2),
+            pre_delay:      p.v(3),
+            freeze:         helpers::param_to_boolean(p.v(4)),
+            dry:            p.v(5),
+            wet:            p.v(6),
+            combs_l,
+            combs_r,
+            all_passes_l,
+            all_passes_r,
+            pre_delay_l:    DelayBuffer::new(pre_delay_len),
+            pre_delay_r:    DelayBuffer::new(pre_delay_len),
+
+            params:         p,
+        }
+    }
+
+    fn update_combs(&mut self) {
+        let (feedback, damp) =
+            if self.freeze {
+                (1.0, 0.0)
+            } else {
+                (self.room_size * SCALE_ROOM + OFFSET_ROOM,
+                 self.damp * SCALE_DAMP)
+            };
+
+        for c in self.combs_l.iter_mut().chain(self.combs_r.iter_mut()) {
+            c.set_feedback(feedback);
+            c.set_damp(damp);
+        }
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        self.update_combs();
+
+        // A frozen reverb keeps its tail and takes no new input:
+        let gain = if self.freeze { 0.0 } else { FIXED_GAIN };
+        let wet  = self.wet * SCALE_WET;
+        let wet1 = wet * (self.width * 0.5 + 0.5);
+        let wet2 = wet * ((1.0 - self.width) * 0.5);
+        let dry  = self.dry;
+
+        let pre_delay =
+            (self.pre_delay as f64 * MAX_PRE_DELAY * self.sample_rate) as usize;
+
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            self.pre_delay_l.feed(frame[0]);
+            self.pre_delay_r.feed(frame[1]);
+            let input =
+                (self.pre_delay_l.tap(pre_delay)
+                 + self.pre_delay_r.tap(pre_delay))
+                * gain;
+
+            let mut out_l = 0.0;
+            let mut out_r = 0.0;
+            for (cl, cr) in self.combs_l.iter_mut().zip(self.combs_r.iter_mut()) {
+                out_l += cl.process(input);
+                out_r += cr.process(input);
+            }
+
+            for (al, ar) in self.all_passes_l.iter_mut()
+                                .zip(self.all_passes_r.iter_mut()) {
+                out_l = al.process(out_l);
+                out_r = ar.process(out_r);
+            }
+
+            let in_l = frame[0];
+            let in_r = frame[1];
+            frame[0] = out_l * wet1 + out_r * wet2 + in_l * dry;
+            frame[1] = out_r * wet1 + out_l * wet2 + in_r * dry;
+        }
+    }
+}
+
+impl Op for Cathedral {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.room_size = helpers::clamp(self.params.inputs[0].calc(regs), 0.0, 1.0);
+        self.damp      = helpers::clamp(self.params.inputs[1].calc(regs), 0.0, 1.0);
+        self.width     = helpers::clamp(self.params.inputs[2].calc(regs), 0.0, 1.0);
+        self.pre_delay = helpers::clamp(self.params.inputs[3].calc(regs), 0.0, 1.0);
+        self.freeze    =
+            helpers::param_to_boolean(self.params.inputs[4].calc(regs));
+        self.dry       = self.params.inputs[5].calc(regs);
+        self.wet       = self.params.inputs[6].calc(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_cathedral(sample_rate: f64) -> Cathedral {
+    Cathedral::new(sample_rate)
+}
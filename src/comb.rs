@@ -0,0 +1,48 @@
+pub struct Comb {
+    feedback:     f32,
+    damp1:        f32,
+    damp2:        f32,
+    filter_store: f32,
+    buffer:       Vec<f32>,
+    buffer_idx:   usize,
+}
+
+impl Comb {
+    pub fn new() -> Self {
+        Comb {
+            feedback:     0.0,
+            damp1:        0.0,
+            damp2:        1.0,
+            filter_store: 0.0,
+            buffer:       vec![0.0],
+            buffer_idx:   0,
+        }
+    }
+
+    pub fn set_feedback(&mut self, fb: f32) {
+        self.feedback = fb;
+    }
+
+    pub fn set_damp(&mut self, damp: f32) {
+        self.damp1 = damp;
+        self.damp2 = 1.0 - damp;
+    }
+
+    pub fn set_buffer_size(&mut self, mut size: usize) {
+        if size < 1 { size = 1; }
+        self.buffer.clear();
+        self.buffer.resize(size, 0.0);
+        self.buffer_idx   = 0;
+        self.filter_store = 0.0;
+    }
+
+    pub fn process(&mut self, input: f32) -> f32 {
+        let output = self.buffer[self.buffer_idx];
+        self.filter_store =
+            output * self.damp2 + self.filter_store * self.damp1;
+        self.buffer[self.buffer_idx] = input + self.filter_store * self.feedback;
+        self.buffer_idx = (self.buffer_idx + 1) % self.buffer.len();
+
+        output
+    }
+}
@@ -0,0 +1,144 @@
+use crate::parameters::*;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Lowest sample rate of the sample and hold in Hz.
+const MIN_RATE  : f64   = 100.0;
+const MAX_BITS  : f32   = 16.0;
+// Compression of the mu-law curve, as in G.711.
+const MU        : f32   = 255.0;
+const NUM_PORTS : usize = 5;
+
+// Quantizes `x` to `steps` steps per unit. Truncating rounds towards
+// negative infinity, which adds the grit of cheap converters. Mu-law
+// spaces the steps logarithmically, so quiet signals keep more detail.
+fn quantize(mode: CrushMode, steps: f32, x: f32) -> f32 {
+    match mode {
+        CrushMode::Round    => (x * steps).round() / steps,
+        CrushMode::Truncate => (x * steps).floor() / steps,
+        CrushMode::MuLaw    => {
+            let norm = (1.0 + MU).ln();
+            let c    = x.signum() * (1.0 + MU * x.abs().min(1.0)).ln() / norm;
+            let q    = (c * steps).round() / steps;
+            q.signum() * ((q.abs() * norm).exp() - 1.0) / MU
+        },
+    }
+}
+
+/// Lo-fi effect: bit depth reduction and sample and hold
+/// sample rate reduction.
+pub struct Crusher {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    mode:           CrushMode,
+    bits:           f32,
+    rate:           f64,
+    dry:            f32,
+    wet:            f32,
+    hold_phase:     f64,
+    hold_l:         f32,
+    hold_r:         f32,
+}
+
+impl Crusher {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("bits",       0.0, 1.0, 7.0 / (MAX_BITS - 1.0));
+        p.input("rate",       0.0, 1.0, 1.0);
+        p.input("dry",        0.0, 1.0, 0.0);
+        p.input("wet",        0.0, 1.0, 1.0);
+        p.input("mode",       0.0, 1.0, CrushMode::Round.into());
+
+        let mut c = Crusher {
+            sample_rate,
+            mode:       CrushMode::Round,
+            bits:       0.0,
+            rate:       0.0,
+            dry:        0.0,
+            wet:        0.0,
+            hold_phase: 1.0,
+            hold_l:     0.0,
+            hold_r:     0.0,
+
+            params:     p,
+        };
+        let v : Vec<f32> = (0..NUM_PORTS).map(|i| c.params.v(i)).collect();
+        c.set(&v);
+        c
+    }
+
+    fn set(&mut self, v: &[f32]) {
+        self.bits = 1.0 + (MAX_BITS - 1.0) * helpers::clamp(v[0], 0.0, 1.0);
+        self.rate =
+            MIN_RATE
+            + (self.sample_rate - MIN_RATE)
+              * helpers::pow(helpers::clamp(v[1], 0.0, 1.0) as f64, 2.0);
+        self.dry  = v[2];
+        self.wet  = v[3];
+        self.mode = v[4].into();
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        // Quantization steps for the signed range -1..1:
+        let steps = helpers::powf(2.0, self.bits - 1.0);
+        let delta = self.rate / self.sample_rate;
+
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            self.hold_phase += delta;
+            if self.hold_phase >= 1.0 {
+                self.hold_phase -= self.hold_phase.floor();
+                self.hold_l = quantize(self.mode, steps, frame[0]);
+                self.hold_r = quantize(self.mode, steps, frame[1]);
+            }
+
+            frame[0] = frame[0] * self.dry + self.hold_l * self.wet;
+            frame[1] = frame[1] * self.dry + self.hold_r * self.wet;
+        }
+    }
+}
+
+impl Op for Crusher {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        let mut v = [0.0; NUM_PORTS];
+        for (i, v) in v.iter_mut().enumerate() {
+            *v = self.params.inputs[i].calc(regs);
+        }
+        self.set(&v);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_crusher(sample_rate: f64) -> Crusher {
+    Crusher::new(sample_rate)
+}
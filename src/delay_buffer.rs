@@ -0,0 +1,55 @@
+/// A ring buffer for delay lines. Samples are written with `feed`
+/// and read back with a delay in samples relative to the last
+/// written sample.
+#[derive(Debug, Clone)]
+pub struct DelayBuffer {
+    data:   Vec<f32>,
+    wr:     usize,
+}
+
+impl DelayBuffer {
+    pub fn new(size: usize) -> Self {
+        let mut db = DelayBuffer {
+            data: vec![],
+            wr:   0,
+        };
+        db.set_size(size);
+        db
+    }
+
+    /// Resizes the buffer, this also clears it.
+    pub fn set_size(&mut self, mut size: usize) {
+        if size < 1 { size = 1; }
+        self.data.clear();
+        self.data.resize(size, 0.0);
+        self.wr = 0;
+    }
+
+    pub fn clear(&mut self) {
+        for s in self.data.iter_mut() { *s = 0.0; }
+    }
+
+    pub fn feed(&mut self, input: f32) {
+        self.wr = (self.wr + 1) % self.data.len();
+        self.data[self.wr] = input;
+    }
+
+    /// Returns the sample that was fed `delay` samples ago. A delay
+    /// of 0 returns the last fed sample. The delay is clamped to the
+    /// buffer length.
+    pub fn tap(&self, delay: usize) -> f32 {
+        let len   = self.data.len();
+        let delay = delay.min(len - 1);
+        self.data[(self.wr + len - delay) % len]
+    }
+
+    /// Like `tap` but with linear interpolation for fractional delays.
+    pub fn tap_linear(&self, delay: f64) -> f32 {
+        let delay = delay.max(0.0);
+        let i     = delay.floor();
+        let fract = (delay - i) as f32;
+        let a     = self.tap(i as usize);
+        let b     = self.tap(i as usize + 1);
+        a + (b - a) * fract
+    }
+}
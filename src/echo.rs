@@ -0,0 +1,179 @@
+use crate::parameters::*;
+use crate::state_variable_filter::*;
+use crate::delay_buffer::DelayBuffer;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Longest delay: 16 sixteenths plus the fine offset at 20 bpm.
+const MAX_DELAY_TIME : f64 = 13.0;
+
+/// A tempo synced stereo delay with cross feedback (ping pong) and
+/// low/high cut filters in the feedback path.
+pub struct Echo {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    bpm:            f32,
+    left_div:       f32,
+    left_fine:      f32,
+    right_div:      f32,
+    right_fine:     f32,
+    feedback:       f32,
+    cross:          f32,
+    low_cut:        f32,
+    high_cut:       f32,
+    dry:            f32,
+    wet:            f32,
+    left_buf:       DelayBuffer,
+    right_buf:      DelayBuffer,
+    low_cut_l:      Filter,
+    low_cut_r:      Filter,
+    high_cut_l:     Filter,
+    high_cut_r:     Filter,
+}
+
+impl Echo {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("bpm",        20.0, 300.0, 120.0);
+        p.input("l_div",      1.0,  16.0,  3.0);
+        p.input("l_fine",     0.0,  1.0,   0.0);
+        p.input("r_div",      1.0,  16.0,  4.0);
+        p.input("r_fine",     0.0,  1.0,   0.0);
+        p.input("fb",         0.0,  1.0,   0.5);
+        p.input("x_fb",       0.0,  1.0,   0.0);
+        p.input("lo_cut",     0.0,  1.0,   0.0);
+        p.input("hi_cut",     0.0,  1.0,   1.0);
+        p.input("dry",        0.0,  1.0,   1.0);
+        p.input("wet",        0.0,  1.0,   0.5);
+
+        let buf_len = (MAX_DELAY_TIME * sample_rate) as usize;
+
+        let mut low_cut_l  = Filter::new(sample_rate);
+        low_cut_l.set_type(FilterType::Highpass);
+        let mut high_cut_l = Filter::new(sample_rate);
+        high_cut_l.set_type(FilterType::Lowpass);
+
+        Echo {
+            sample_rate,
+            bpm:        p.v(0),
+            left_div:   p.v(1),
+            left_fine:  p.v(2),
+            right_div:  p.v(3),
+            right_fine: p.v(4),
+            feedback:   p.v(5),
+            cross:      p.v(6),
+            low_cut:    helpers::param_to_frequency(p.v(7)),
+            high_cut:   helpers::param_to_frequency(p.v(8)),
+            dry:        p.v(9),
+            wet:        p.v(10),
+            left_buf:   DelayBuffer::new(buf_len),
+            right_buf:  DelayBuffer::new(buf_len),
+            low_cut_l,
+            low_cut_r:  low_cut_l,
+            high_cut_l,
+            high_cut_r: high_cut_l,
+
+            params:     p,
+        }
+    }
+
+    /// Returns the delay in samples for a number of sixteenth notes.
+    fn delay_samples(&self, div: f32, fine: f32) -> f64 {
+        let sixteenths = div.round() as f64 + fine as f64;
+        sixteenths * (60.0 / 4.0) / (self.bpm.max(1.0) as f64) * self.sample_rate
+    }
+
+    pub fn clear(&mut self) {
+        self.left_buf.clear();
+        self.right_buf.clear();
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        let left_delay  = self.delay_samples(self.left_div,  self.left_fine);
+        let right_delay = self.delay_samples(self.right_div, self.right_fine);
+
+        self.low_cut_l.set_freq(self.low_cut);
+        self.low_cut_r.set_freq(self.low_cut);
+        self.high_cut_l.set_freq(self.high_cut);
+        self.high_cut_r.set_freq(self.high_cut);
+
+        // The tap is read before the current sample is fed, so a
+        // delay of 0 already returns the previous sample. Hence the
+        // delay is one sample less:
+        let left_delay  = (left_delay  - 1.0).max(0.0);
+        let right_delay = (right_delay - 1.0).max(0.0);
+
+        let fb_direct = self.feedback * (1.0 - self.cross);
+        let fb_cross  = self.feedback * self.cross;
+
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            let left  = self.left_buf.tap_linear(left_delay);
+            let right = self.right_buf.tap_linear(right_delay);
+
+            let fb_left  = self.high_cut_l.next(self.low_cut_l.next(left));
+            let fb_right = self.high_cut_r.next(self.low_cut_r.next(right));
+
+            self.left_buf.feed(
+                frame[0] + fb_left * fb_direct + fb_right * fb_cross);
+            self.right_buf.feed(
+                frame[1] + fb_right * fb_direct + fb_left * fb_cross);
+
+            frame[0] = frame[0] * self.dry + left  * self.wet;
+            frame[1] = frame[1] * self.dry + right * self.wet;
+        }
+    }
+}
+
+impl Op for Echo {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.bpm        = self.params.inputs[0].calc(regs);
+        self.left_div   = self.params.inputs[1].calc(regs);
+        self.left_fine  = self.params.inputs[2].calc(regs);
+        self.right_div  = self.params.inputs[3].calc(regs);
+        self.right_fine = self.params.inputs[4].calc(regs);
+        self.feedback   = helpers::clamp(self.params.inputs[5].calc(regs), 0.0, 1.0);
+        self.cross      = helpers::clamp(self.params.inputs[6].calc(regs), 0.0, 1.0);
+        self.low_cut    =
+            helpers::param_to_frequency(self.params.inputs[7].calc(regs));
+        self.high_cut   =
+            helpers::param_to_frequency(self.params.inputs[8].calc(regs));
+        self.dry        = self.params.inputs[9].calc(regs);
+        self.wet        = self.params.inputs[10].calc(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_echo(sample_rate: f64) -> Echo {
+    Echo::new(sample_rate)
+}
@@ -0,0 +1,368 @@
+use crate::synth_device::*;
+use crate::envelope::*;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Scaling of the operator outputs when they are used as phase
+// modulation input, taken over from WaveSabre.
+const MOD_SCALE : f64 = 13.25;
+
+pub struct FalconParams {
+    dev_params:             SynthDeviceParams,
+    params:                 SignalIOParams,
+    osc1_waveform:          f32,
+    osc1_ratio_coarse:      f32,
+    osc1_ratio_fine:        f32,
+    osc1_feedback:          f32,
+    osc1_feed_forward:      f32,
+    osc1_attack:            f32,
+    osc1_decay:             f32,
+    osc1_sustain:           f32,
+    osc1_release:           f32,
+    osc2_waveform:          f32,
+    osc2_ratio_coarse:      f32,
+    osc2_ratio_fine:        f32,
+    osc2_feedback:          f32,
+    osc2_attack:            f32,
+    osc2_decay:             f32,
+    osc2_sustain:           f32,
+    osc2_release:           f32,
+    pitch_attack:           f32,
+    pitch_decay:            f32,
+    pitch_sustain:          f32,
+    pitch_release:          f32,
+    pitch_env_amt1:         f32,
+    pitch_env_amt2:         f32,
+    vel_amp:                f32,
+}
+
+impl FalconParams {
+    pub fn new() -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("o1_wav",     0.0, 1.0, 0.0);
+        p.input("o1_rc",      0.0, 1.0, 0.0);
+        p.input("o1_rf",      0.0, 1.0, 0.0);
+        p.input("o1_fb",      0.0, 1.0, 0.0);
+        p.input("o1_ff",      0.0, 1.0, 0.0);
+        p.input("o1_a",       0.0, 1.0, 1.0);
+        p.input("o1_d",       0.0, 1.0, 5.0);
+        p.input("o1_s",       0.0, 1.0, 0.5);
+        p.input("o1_r",       0.0, 1.0, 1.5);
+        p.input("o2_wav",     0.0, 1.0, 0.0);
+        p.input("o2_rc",      0.0, 1.0, 0.0);
+        p.input("o2_rf",      0.0, 1.0, 0.0);
+        p.input("o2_fb",      0.0, 1.0, 0.0);
+        p.input("o2_a",       0.0, 1.0, 1.0);
+        p.input("o2_d",       0.0, 1.0, 5.0);
+        p.input("o2_s",       0.0, 1.0, 0.5);
+        p.input("o2_r",       0.0, 1.0, 1.5);
+        p.input("pit_a",      0.0, 1.0, 1.0);
+        p.input("pit_d",      0.0, 1.0, 5.0);
+        p.input("pit_s",      0.0, 1.0, 0.5);
+        p.input("pit_r",      0.0, 1.0, 1.5);
+        p.input("pit_eamt1",  0.0, 1.0, 0.5);
+        p.input("pit_eamt2",  0.0, 1.0, 0.5);
+        p.input("vel_amp",    0.0, 1.0, 1.0);
+
+        let dev_params = SynthDeviceParams::new_with_params(&mut p);
+
+        FalconParams {
+            dev_params,
+            osc1_waveform:      p.v(0),
+            osc1_ratio_coarse:  p.v(1),
+            osc1_ratio_fine:    p.v(2),
+            osc1_feedback:      p.v(3),
+            osc1_feed_forward:  p.v(4),
+            osc1_attack:        p.v(5),
+            osc1_decay:         p.v(6),
+            osc1_sustain:       p.v(7),
+            osc1_release:       p.v(8),
+            osc2_waveform:      p.v(9),
+            osc2_ratio_coarse:  p.v(10),
+            osc2_ratio_fine:    p.v(11),
+            osc2_feedback:      p.v(12),
+            osc2_attack:        p.v(13),
+            osc2_decay:         p.v(14),
+            osc2_sustain:       p.v(15),
+            osc2_release:       p.v(16),
+            pitch_attack:       p.v(17),
+            pitch_decay:        p.v(18),
+            pitch_sustain:      p.v(19),
+            pitch_release:      p.v(20),
+            pitch_env_amt1:     p.v(21),
+            pitch_env_amt2:     p.v(22),
+            vel_amp:            p.v(23),
+
+            params:             p,
+        }
+    }
+}
+
+impl AsMut<SignalIOParams> for FalconParams {
+    fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+}
+
+impl VoiceParams for FalconParams {
+    fn update(&mut self) -> SynthDeviceParams {
+        let v = &self.params.values;
+
+        self.osc1_waveform      = v[0];
+        self.osc1_ratio_coarse  = v[1];
+        self.osc1_ratio_fine    = v[2];
+        self.osc1_feedback      = v[3];
+        self.osc1_feed_forward  = v[4];
+        self.osc1_attack        = helpers::scalar_to_env_value(v[5]);
+        self.osc1_decay         = helpers::scalar_to_env_value(v[6]);
+        self.osc1_sustain       = v[7];
+        self.osc1_release       = helpers::scalar_to_env_value(v[8]);
+        self.osc2_waveform      = v[9];
+        self.osc2_ratio_coarse  = v[10];
+        self.osc2_ratio_fine    = v[11];
+        self.osc2_feedback      = v[12];
+        self.osc2_attack        = helpers::scalar_to_env_value(v[13]);
+        self.osc2_decay         = helpers::scalar_to_env_value(v[14]);
+        self.osc2_sustain       = v[15];
+        self.osc2_release       = helpers::scalar_to_env_value(v[16]);
+        self.pitch_attack       = helpers::scalar_to_env_value(v[17]);
+        self.pitch_decay        = helpers::scalar_to_env_value(v[18]);
+        self.pitch_sustain      = v[19];
+        self.pitch_release      = helpers::scalar_to_env_value(v[20]);
+        self.pitch_env_amt1     = (v[21] - 0.5) * 2.0 * 36.0;
+        self.pitch_env_amt2     = (v[22] - 0.5) * 2.0 * 36.0;
+        self.vel_amp            = v[23];
+
+        self.dev_params.update(&self.params);
+        self.dev_params
+    }
+}
+
+// Mixes between a sine and a bright square like waveform.
+fn operator_wave(phase: f64, waveform: f32) -> f64 {
+    let wf = waveform as f64;
+    helpers::fast_sin(phase) * (1.0 - wf)
+    + helpers::square_35(phase) * wf
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct FalconVoice {
+    sample_rate: f64,
+    osc1_phase:  f64,
+    osc2_phase:  f64,
+    osc1_output: f64,
+    osc2_output: f64,
+    osc1_env:    Envelope,
+    osc2_env:    Envelope,
+    pitch_env:   Envelope,
+}
+
+impl FalconVoice {
+    pub fn ratio_scalar(&self, coarse: f32, fine: f32) -> f64 {
+        let real_coarse = (coarse as f64 * 3.99 * 5.0).floor();
+        (if real_coarse > 0.0 { real_coarse } else { 0.5 }) + fine as f64
+    }
+}
+
+impl Voice<FalconParams> for FalconVoice {
+    fn new(sample_rate: f64) -> Self {
+        FalconVoice {
+            sample_rate,
+            osc1_phase:  0.0,
+            osc2_phase:  0.0,
+            osc1_output: 0.0,
+            osc2_output: 0.0,
+            osc1_env:    Envelope::new(sample_rate),
+            osc2_env:    Envelope::new(sample_rate),
+            pitch_env:   Envelope::new(sample_rate),
+        }
+    }
+    fn note_on(&mut self, data: &mut VoiceData, params: &mut FalconParams, note: i32, velocity: i32, detune: f32, pan: f32) {
+        data.note_on(note, velocity, detune, pan);
+
+        self.osc1_env.attack    = params.osc1_attack;
+        self.osc1_env.decay     = params.osc1_decay;
+        self.osc1_env.sustain   = params.osc1_sustain;
+        self.osc1_env.release   = params.osc1_release;
+        self.osc1_env.trigger();
+
+        self.osc2_env.attack    = params.osc2_attack;
+        self.osc2_env.decay     = params.osc2_decay;
+        self.osc2_env.sustain   = params.osc2_sustain;
+        self.osc2_env.release   = params.osc2_release;
+        self.osc2_env.trigger();
+
+        self.pitch_env.attack   = params.pitch_attack;
+        self.pitch_env.decay    = params.pitch_decay;
+        self.pitch_env.sustain  = params.pitch_sustain;
+        self.pitch_env.release  = params.pitch_release;
+        self.pitch_env.trigger();
+
+        self.osc1_phase  = 0.0;
+        self.osc2_phase  = 0.0;
+        self.osc1_output = 0.0;
+        self.osc2_output = 0.0;
+    }
+    fn note_off(&mut self, data: &mut VoiceData, _params: &mut FalconParams) {
+        data.note_off();
+        self.osc1_env.off();
+        self.osc2_env.off();
+        self.pitch_env.off();
+    }
+    fn note_slide(&mut self, data: &mut VoiceData, _params: &mut FalconParams, slide: f32, note: i32) {
+        data.note_slide(slide, note);
+    }
+    fn get_note(&mut self, data: &mut VoiceData, _params: &mut FalconParams) -> f64 {
+        data.get_note()
+    }
+    fn is_finished(&self, _data: &VoiceData, _params: &FalconParams) -> bool {
+        self.osc2_env.state == EnvelopeState::Finished
+    }
+    fn level(&self, _data: &VoiceData, _params: &FalconParams) -> f32 {
+        self.osc2_env.get_value()
+    }
+    fn run(&mut self,
+           data: &mut VoiceData,
+           params: &mut FalconParams,
+           _song_pos: f64,
+           sample_num: usize,
+           out_offs: usize,
+           outputs: &mut [f32]) {
+
+        let two_pi = 2.0 * std::f64::consts::PI;
+
+        let osc1_ratio =
+            self.ratio_scalar(params.osc1_ratio_coarse, params.osc1_ratio_fine);
+        let osc2_ratio =
+            self.ratio_scalar(params.osc2_ratio_coarse, params.osc2_ratio_fine);
+
+        let osc1_feedback     = params.osc1_feedback as f64 * MOD_SCALE;
+        let osc2_feedback     = params.osc2_feedback as f64 * MOD_SCALE;
+        let osc1_feed_forward = params.osc1_feed_forward as f64 * MOD_SCALE;
+
+        let amp       = helpers::volume_to_scalar(data.master_level)
+                        * (1.0 - params.vel_amp * (1.0 - data.velocity));
+        let pan_left  = helpers::pan_to_scalar_left(data.pan);
+        let pan_right = helpers::pan_to_scalar_right(data.pan);
+
+        for i in 0..sample_num {
+            let base_note : f64 = data.get_note();
+            let pitch_env       = self.pitch_env.get_value() as f64;
+
+            let osc1_input =
+                self.osc1_phase + self.osc1_output * osc1_feedback;
+            self.osc1_output =
+                operator_wave(osc1_input, params.osc1_waveform)
+                * self.osc1_env.get_value() as f64;
+
+            let osc2_input =
+                self.osc2_phase
+                + self.osc2_output * osc2_feedback
+                + self.osc1_output * osc1_feed_forward;
+            self.osc2_output =
+                operator_wave(osc2_input, params.osc2_waveform)
+                * self.osc2_env.get_value() as f64;
+
+            let s = self.osc2_output as f32 * amp;
+            outputs[(out_offs + i) * 2]     += s * pan_left;
+            outputs[(out_offs + i) * 2 + 1] += s * pan_right;
+
+            let osc1_freq =
+                helpers::note_to_freq(
+                    base_note + pitch_env * params.pitch_env_amt1 as f64)
+                * osc1_ratio;
+            let osc2_freq =
+                helpers::note_to_freq(
+                    base_note + pitch_env * params.pitch_env_amt2 as f64)
+                * osc2_ratio;
+
+            self.osc1_phase =
+                (self.osc1_phase + two_pi * osc1_freq / self.sample_rate) % two_pi;
+            self.osc2_phase =
+                (self.osc2_phase + two_pi * osc2_freq / self.sample_rate) % two_pi;
+
+            self.osc1_env.next();
+            self.osc2_env.next();
+            self.pitch_env.next();
+        }
+    }
+}
+
+impl Op for SynthDevice<FalconVoice, FalconParams> {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.params.ports.clone(),
+            input_values:     self.params.params.inputs.clone(),
+            input_defaults:   self.params.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, ev: &Event) {
+        self.op_event(ev);
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.exec_params(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let mut f : [f32; 1] = [0.0; 1];
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.run(0.0, num_samples, &mut f, buf);
+    }
+}
+
+pub fn new_falcon(sample_rate: f64) -> SynthDevice<FalconVoice, FalconParams> {
+    let params     = FalconParams::new();
+    let dev_params = params.dev_params;
+    let mut sd : SynthDevice<FalconVoice, FalconParams> =
+        SynthDevice::new(sample_rate, params);
+    sd.set_dev_params(&dev_params);
+    sd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_voice(v: &mut FalconVoice, data: &mut VoiceData,
+                 params: &mut FalconParams, len: usize) {
+        let mut out = vec![0.0; len * 2];
+        v.run(data, params, 0.0, len, 0, &mut out);
+    }
+
+    #[test]
+    fn test_finished_with_carrier() {
+        let mut params = FalconParams::new();
+        params.osc1_release = 1000.0;
+        params.osc2_release = 20.0;
+
+        let mut data = VoiceData::new(44100.0);
+        let mut v    = FalconVoice::new(44100.0);
+        v.note_on(&mut data, &mut params, 60, 127, 0.0, 0.5);
+        run_voice(&mut v, &mut data, &mut params, 441);
+        v.note_off(&mut data, &mut params);
+
+        run_voice(&mut v, &mut data, &mut params, 441);
+        assert!(!v.is_finished(&data, &params));
+
+        // The voice ends with the carrier envelope, the modulator
+        // is inaudible on its own:
+        run_voice(&mut v, &mut data, &mut params, 882);
+        assert!(v.is_finished(&data, &params));
+        assert_eq!(v.osc1_env.state, EnvelopeState::Release);
+    }
+}
@@ -87,6 +87,10 @@ pub fn db_to_scalar(db: f32) -> f32 {
     powf(2.0, db / 6.0)
 }
 
+pub fn scalar_to_db(scalar: f32) -> f32 {
+    6.0 * scalar.log2()
+}
+
 pub fn env_value_to_scalar(value: f32) -> f32 {
     (value - 1.0).sqrt() / 5000.0
 }
@@ -104,6 +108,10 @@ pub fn scalar_to_volume(scalar: f32) -> f32 {
     scalar.sqrt() / 0.4
 }
 
+pub fn velocity_to_scalar(velocity: i32) -> f32 {
+    clamp(velocity as f32 / 127.0, 0.0, 1.0)
+}
+
 pub fn param_to_boolean(value: f32) -> bool { value >= 0.5 }
 pub fn boolean_to_param(b: bool)    -> f32  { if b { 1.0 } else { 0.0 } }
 
@@ -155,6 +163,18 @@ pub fn unisono_to_param(unisono: i32) -> f32 {
     (unisono - 1) as f32 / 15.0
 }
 
+pub fn param_to_velocity(param: f32) -> i32 {
+    (clamp(param, 0.0, 1.0) * 127.0).round() as i32
+}
+
+pub fn param_to_polyphony(param: f32) -> usize {
+    (param * 255.0).round() as usize + 1
+}
+
+pub fn polyphony_to_param(polyphony: usize) -> f32 {
+    (polyphony - 1) as f32 / 255.0
+}
+
 pub fn param_to_vibrato_freq(param: f32) -> f64 {
     (pow(param as f64, 2.0) + 0.1) * 70.0
 }
@@ -164,6 +184,31 @@ pub fn vibrato_freq_to_param(vf: f64) -> f32 {
     if d >= 0.0 { d.sqrt() as f32 } else { 0.0 }
 }
 
+pub fn param_to_lfo_freq(param: f32) -> f64 {
+    0.01 + 10.0 * pow(param as f64, 2.0)
+}
+
+pub fn lfo_freq_to_param(freq: f64) -> f32 {
+    let d = (freq - 0.01) / 10.0;
+    if d >= 0.0 { d.sqrt() as f32 } else { 0.0 }
+}
+
+pub fn param_to_rise_time(param: f32) -> f64 {
+    4.0 * pow(param as f64, 2.0)
+}
+
+pub fn rise_time_to_param(rt: f64) -> f32 {
+    (rt / 4.0).sqrt() as f32
+}
+
+pub fn param_to_bend_range(param: f32) -> f32 {
+    (param * 24.0).round()
+}
+
+pub fn bend_range_to_param(range: f32) -> f32 {
+    range / 24.0
+}
+
 pub fn pan_to_scalar_left(pan: f32) -> f32 {
     (1.0 - pan).sqrt()
 }
@@ -238,6 +283,14 @@ pub struct SignalIOParams {
     pub inputs:     Vec<OpIn>,
     pub defaults:   Vec<OpIn>,
     pub ports:      Vec<OpPort>,
+    pub ranges:     Vec<(f32, f32)>,
+    pub cc_map:     Vec<(u8, usize)>,
+    /// Controller values of the ports mapped to a MIDI CC. They
+    /// take precedence over `inputs` without replacing them.
+    pub cc_values:  Vec<Option<f32>>,
+    /// The current port values, as calculated by `calc_all` and
+    /// updated on control changes.
+    pub values:     Vec<f32>,
 }
 
 impl SignalIOParams {
@@ -246,6 +299,10 @@ impl SignalIOParams {
             inputs: Vec::new(),
             defaults: Vec::new(),
             ports: Vec::new(),
+            ranges: Vec::new(),
+            cc_map: Vec::new(),
+            cc_values: Vec::new(),
+            values: Vec::new(),
         }
     }
 
@@ -257,6 +314,61 @@ impl SignalIOParams {
         self.inputs.push(OpIn::Constant(default));
         self.defaults.push(OpIn::Constant(default));
         self.ports.push(OpPort::new(name, min, max));
+        self.ranges.push((min, max));
+        self.cc_values.push(None);
+        self.values.push(default);
+    }
+
+    /// Calculates the value of the port `idx`.
+    pub fn calc(&self, idx: usize, regs: &[f32]) -> f32 {
+        match self.cc_values[idx] {
+            Some(v) => v,
+            None    => self.inputs[idx].calc(regs),
+        }
+    }
+
+    /// Calculates all port values into `values`.
+    pub fn calc_all(&mut self, regs: &[f32]) {
+        for i in 0..self.values.len() {
+            self.values[i] = self.calc(i, regs);
+        }
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.ports.iter().position(|p| p.name == name)
+    }
+
+    pub fn map_cc(&mut self, cc: u8, name: &str) -> bool {
+        if let Some(i) = self.index_of(name) {
+            self.cc_map.push((cc, i));
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn unmap_cc(&mut self, cc: u8) {
+        for (c, i) in self.cc_map.iter() {
+            if *c == cc { self.cc_values[*i] = None; }
+        }
+        self.cc_map.retain(|(c, _i)| *c != cc);
+    }
+
+    // Sets all ports mapped to `cc` to the controller value,
+    // which is scaled from 0.0-1.0 to the range of the port.
+    // The port inputs are kept, they apply again once the port
+    // is unmapped. Returns true if any port is mapped to `cc`.
+    pub fn control_change(&mut self, cc: u8, value: f32) -> bool {
+        let mut mapped = false;
+        for (c, i) in self.cc_map.iter() {
+            if *c != cc { continue; }
+            let (min, max) = self.ranges[*i];
+            let v = min + (max - min) * value;
+            self.cc_values[*i] = Some(v);
+            self.values[*i]    = v;
+            mapped = true;
+        }
+        mapped
     }
 
     pub fn set(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
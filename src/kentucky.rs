@@ -0,0 +1,343 @@
+use crate::synth_device::*;
+use crate::sample_player::*;
+use crate::sample_loader;
+use crate::envelope::*;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+use std::sync::Arc;
+
+pub const NUM_SLOTS         : usize = 8;
+pub const NUM_CHOKE_GROUPS  : usize = 4;
+const PORTS_PER_SLOT        : usize = 6;
+// Release time in milliseconds of a voice that was choked.
+const CHOKE_TIME            : f32   = 5.0;
+
+// General MIDI percussion notes for the default slot layout:
+// kick, snare, clap, closed hat, open hat, low tom, high tom, crash.
+const DEFAULT_NOTES         : [i32; NUM_SLOTS] = [36, 38, 39, 42, 46, 45, 48, 49];
+const DEFAULT_CHOKE         : [usize; NUM_SLOTS] = [0, 0, 0, 1, 1, 0, 0, 0];
+
+#[derive(Debug, Clone)]
+pub struct KentuckySlot {
+    pub note:        i32,
+    pub tune:        f32,
+    pub level:       f32,
+    pub pan:         f32,
+    pub decay:       f32,
+    /// Choke group of the slot, 0 means the slot is not choked.
+    pub choke:       usize,
+    pub sample:      Arc<Vec<f32>>,
+    /// Sample rate of the sample, 0.0 means the engine sample rate.
+    pub sample_rate: f64,
+}
+
+impl KentuckySlot {
+    fn new(note: i32, choke: usize) -> Self {
+        KentuckySlot {
+            note,
+            tune:        0.0,
+            level:       1.0,
+            pan:         0.5,
+            decay:       helpers::scalar_to_env_value(0.5),
+            choke,
+            sample:      Arc::new(Vec::new()),
+            sample_rate: 0.0,
+        }
+    }
+}
+
+pub struct KentuckyParams {
+    dev_params: SynthDeviceParams,
+    params:     SignalIOParams,
+    slots:      Vec<KentuckySlot>,
+    choke_gen:  [u64; NUM_CHOKE_GROUPS + 1],
+    vel_amp:    f32,
+}
+
+impl KentuckyParams {
+    pub fn new() -> Self {
+        let mut p = SignalIOParams::new();
+
+        let mut slots = Vec::new();
+        for i in 0..NUM_SLOTS {
+            let s = i + 1;
+            p.input(&format!("s{}_note", s), 0.0, 127.0, DEFAULT_NOTES[i] as f32);
+            p.input(&format!("s{}_tune", s), 0.0, 1.0,   0.5);
+            p.input(&format!("s{}_lvl",  s), 0.0, 1.0,   1.0);
+            p.input(&format!("s{}_pan",  s), 0.0, 1.0,   0.5);
+            p.input(&format!("s{}_dcy",  s), 0.0, 1.0,   0.5);
+            p.input(&format!("s{}_chk",  s), 0.0, NUM_CHOKE_GROUPS as f32,
+                    DEFAULT_CHOKE[i] as f32);
+
+            slots.push(KentuckySlot::new(DEFAULT_NOTES[i], DEFAULT_CHOKE[i]));
+        }
+
+        p.input("vel_amp", 0.0, 1.0, 1.0);
+        let vel_amp = p.v(NUM_SLOTS * PORTS_PER_SLOT);
+
+        let dev_params = SynthDeviceParams::new_with_params(&mut p);
+
+        KentuckyParams {
+            dev_params,
+            params:    p,
+            slots,
+            choke_gen: [0; NUM_CHOKE_GROUPS + 1],
+            vel_amp,
+        }
+    }
+
+    /// Returns the slot that is triggered by `note`. If several
+    /// slots are mapped to the same note, the first one wins.
+    pub fn slot_for_note(&self, note: i32) -> Option<usize> {
+        self.slots.iter().position(|s| s.note == note)
+    }
+
+    pub fn set_slot_sample(&mut self, slot: usize, data: Vec<f32>) {
+        if slot < NUM_SLOTS {
+            self.slots[slot].sample      = Arc::new(data);
+            self.slots[slot].sample_rate = 0.0;
+        }
+    }
+
+    pub fn load_slot_sample(&mut self, slot: usize, file: &str)
+        -> Result<(), sample_loader::LoadError>
+    {
+        let sample = sample_loader::load_wav(file)?;
+        self.set_slot_sample(slot, sample.to_mono());
+        if slot < NUM_SLOTS {
+            self.slots[slot].sample_rate = sample.sample_rate as f64;
+        }
+        Ok(())
+    }
+}
+
+impl AsMut<SignalIOParams> for KentuckyParams {
+    fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+}
+
+impl VoiceParams for KentuckyParams {
+    fn update(&mut self) -> SynthDeviceParams {
+        let v = &self.params.values;
+
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            let o = i * PORTS_PER_SLOT;
+
+            slot.note  = v[o].round() as i32;
+            slot.tune  = (v[o + 1] - 0.5) * 2.0 * 24.0;
+            slot.level = v[o + 2];
+            slot.pan   = v[o + 3];
+            slot.decay = helpers::scalar_to_env_value(v[o + 4]);
+            slot.choke = (v[o + 5].round() as usize).min(NUM_CHOKE_GROUPS);
+        }
+        self.vel_amp = v[NUM_SLOTS * PORTS_PER_SLOT];
+
+        self.dev_params.update(&self.params);
+        self.dev_params
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct KentuckyVoice {
+    player:     SamplePlayer,
+    amp_env:    Envelope,
+    slot:       Option<usize>,
+    choke:      usize,
+    choke_gen:  u64,
+    amp:        f32,
+    pan:        f32,
+}
+
+impl Voice<KentuckyParams> for KentuckyVoice {
+    fn new(sample_rate: f64) -> Self {
+        let mut player = SamplePlayer::new(sample_rate);
+        player.loop_mode = LoopMode::Disabled;
+
+        KentuckyVoice {
+            player,
+            amp_env:    Envelope::new(sample_rate),
+            slot:       None,
+            choke:      0,
+            choke_gen:  0,
+            amp:        0.0,
+            pan:        0.5,
+        }
+    }
+    fn note_on(&mut self, data: &mut VoiceData, params: &mut KentuckyParams, note: i32, velocity: i32, detune: f32, pan: f32) {
+        data.note_on(note, velocity, detune, pan);
+
+        self.slot = params.slot_for_note(note);
+        let slot =
+            match self.slot {
+                Some(s) if !params.slots[s].sample.is_empty() => s,
+                _ => {
+                    self.player.is_active = false;
+                    return;
+                },
+            };
+
+        // The note id of the last hit in a choke group is the group's
+        // generation, voices that started with an older one are choked.
+        // Unisono voices of the same hit share the note id:
+        self.choke     = params.slots[slot].choke;
+        self.choke_gen = data.note_id;
+        if self.choke > 0 {
+            params.choke_gen[self.choke] = data.note_id;
+        }
+
+        let s = &params.slots[slot];
+
+        // Drums have no sustain stage, the decay is also used as release
+        // so that a choked or stolen voice does not stop abruptly.
+        self.amp_env.attack  = 1.0;
+        self.amp_env.decay   = s.decay;
+        self.amp_env.sustain = 0.0;
+        self.amp_env.release = s.decay;
+        self.amp_env.trigger();
+
+        self.amp =
+            s.level * (1.0 - params.vel_amp * (1.0 - data.velocity));
+        self.pan = helpers::clamp(s.pan + (data.pan - 0.5), 0.0, 1.0);
+
+        self.player.sample_data = s.sample.clone();
+        self.player.set_source_sample_rate(s.sample_rate);
+        self.player.run_prep();
+        self.player.calc_pitch(s.tune as f64 + data.detune as f64);
+        self.player.init_pos();
+    }
+    fn note_off(&mut self, data: &mut VoiceData, _params: &mut KentuckyParams) {
+        // One shot: the sample plays until its decay is finished.
+        data.note_off();
+    }
+    fn note_slide(&mut self, data: &mut VoiceData, _params: &mut KentuckyParams, slide: f32, note: i32) {
+        data.note_slide(slide, note);
+    }
+    fn get_note(&mut self, data: &mut VoiceData, _params: &mut KentuckyParams) -> f64 {
+        data.get_note()
+    }
+    fn is_finished(&self, _data: &VoiceData, _params: &KentuckyParams) -> bool {
+        !self.player.is_active
+        || self.amp_env.state == EnvelopeState::Sustain
+        || self.amp_env.state == EnvelopeState::Finished
+    }
+    fn level(&self, _data: &VoiceData, _params: &KentuckyParams) -> f32 {
+        if self.player.is_active { self.amp_env.get_value() } else { 0.0 }
+    }
+    fn run(&mut self,
+           data: &mut VoiceData,
+           params: &mut KentuckyParams,
+           _song_pos: f64,
+           sample_num: usize,
+           out_offs: usize,
+           outputs: &mut [f32]) {
+
+        if self.is_finished(data, params) {
+            return;
+        }
+
+        if self.choke > 0
+           && params.choke_gen[self.choke] != self.choke_gen
+           && self.amp_env.state != EnvelopeState::Release {
+
+            self.amp_env.release = CHOKE_TIME;
+            self.amp_env.off();
+        }
+
+        let amp       = helpers::volume_to_scalar(data.master_level) * self.amp;
+        let pan_left  = helpers::pan_to_scalar_left(self.pan);
+        let pan_right = helpers::pan_to_scalar_right(self.pan);
+
+        for i in 0..sample_num {
+            let s = self.player.next() * self.amp_env.get_value() * amp;
+            outputs[(out_offs + i) * 2]     += s * pan_left;
+            outputs[(out_offs + i) * 2 + 1] += s * pan_right;
+
+            self.amp_env.next();
+
+            if self.is_finished(data, params) {
+                break;
+            }
+        }
+    }
+}
+
+impl Op for SynthDevice<KentuckyVoice, KentuckyParams> {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.params.ports.clone(),
+            input_values:     self.params.params.inputs.clone(),
+            input_defaults:   self.params.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, ev: &Event) {
+        self.op_event(ev);
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.exec_params(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let mut f : [f32; 1] = [0.0; 1];
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.run(0.0, num_samples, &mut f, buf);
+    }
+}
+
+pub fn new_kentucky(sample_rate: f64) -> SynthDevice<KentuckyVoice, KentuckyParams> {
+    let params     = KentuckyParams::new();
+    let dev_params = params.dev_params;
+    let mut sd : SynthDevice<KentuckyVoice, KentuckyParams> =
+        SynthDevice::new(sample_rate, params);
+    sd.set_dev_params(&dev_params);
+    sd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_device(sd: &mut SynthDevice<KentuckyVoice, KentuckyParams>, len: usize) {
+        let mut out = vec![0.0; 128];
+        for _ in 0..(len / 64) {
+            sd.run(0.0, 64, &mut [], &mut out);
+        }
+    }
+
+    #[test]
+    fn test_choke_group_with_unisono() {
+        let mut sd = new_kentucky(44100.0);
+        // The closed and open hat slots share choke group 1:
+        sd.params.set_slot_sample(3, vec![0.5; 44100]);
+        sd.params.set_slot_sample(4, vec![0.5; 44100]);
+        sd.set_input("v_uniso", OpIn::Constant(helpers::unisono_to_param(2)), false);
+        sd.exec_params(&[]);
+
+        // The unisono voices of a hit don't choke each other:
+        sd.note_on(46, 127, 0);
+        sd.note_off(46, 1);
+        run_device(&mut sd, 448);
+        assert_eq!(sd.active_voices(), 2);
+
+        // The closed hat chokes both open hat voices:
+        sd.note_on(42, 127, 0);
+        sd.note_off(42, 1);
+        run_device(&mut sd, 896);
+        assert_eq!(sd.active_voices(), 2);
+        assert_eq!(sd.params.choke_gen[1], 2);
+    }
+}
@@ -0,0 +1,211 @@
+use crate::parameters::*;
+use crate::biquad::Biquad;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Gain range of the shelf and peak bands in dB.
+const GAIN_RANGE : f32   = 18.0;
+const NUM_BANDS  : usize = 7;
+const NUM_PORTS  : usize = 20;
+
+struct LevellerBand {
+    filter_type: BiquadType,
+    // Offset of the band's first port, the ports are
+    // frequency, then gain (if the band has one), then Q.
+    port_offs:   usize,
+    has_gain:    bool,
+    enabled:     bool,
+    left:        Biquad,
+    right:       Biquad,
+}
+
+impl LevellerBand {
+    fn new(sample_rate: f64, filter_type: BiquadType, port_offs: usize) -> Self {
+        let mut left = Biquad::new(sample_rate);
+        left.set_type(filter_type);
+
+        LevellerBand {
+            filter_type,
+            port_offs,
+            has_gain: filter_type != BiquadType::Lowpass
+                      && filter_type != BiquadType::Highpass,
+            enabled:  true,
+            left,
+            right:    left,
+        }
+    }
+
+    fn num_ports(&self) -> usize { if self.has_gain { 3 } else { 2 } }
+
+    fn set(&mut self, v: &[f32]) {
+        let o        = self.port_offs;
+        let freq     = helpers::param_to_frequency(v[o]);
+        let (gain, q) =
+            if self.has_gain {
+                (helpers::param_to_db(v[o + 1], GAIN_RANGE),
+                 helpers::param_to_q(v[o + 2]))
+            } else {
+                (0.0, helpers::param_to_q(v[o + 1]))
+            };
+
+        // Cuts at the ends of the frequency range and bands without
+        // gain do not change the signal and are skipped:
+        let enabled =
+            match self.filter_type {
+                BiquadType::Highpass => v[o] > 0.0,
+                BiquadType::Lowpass  => v[o] < 1.0,
+                _                    => gain != 0.0,
+            };
+
+        // A skipped band's filter state is stale, starting from
+        // silence again prevents a click:
+        if enabled && !self.enabled {
+            self.left.reset();
+            self.right.reset();
+        }
+        self.enabled = enabled;
+
+        for bq in [&mut self.left, &mut self.right].iter_mut() {
+            bq.set_freq(freq);
+            bq.set_gain(gain);
+            bq.set_q(q);
+        }
+    }
+}
+
+/// A seven band parametric EQ: low cut, low shelf, three peaking
+/// bands, high shelf and high cut.
+pub struct Leveller {
+    params:         SignalIOParams,
+    bands:          Vec<LevellerBand>,
+    output_gain:    f32,
+}
+
+impl Leveller {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("lc_f",       0.0, 1.0, 0.0);
+        p.input("lc_q",       0.0, 1.0, 0.28);
+        p.input("ls_f",       0.0, 1.0, 0.063);
+        p.input("ls_g",       0.0, 1.0, 0.5);
+        p.input("ls_q",       0.0, 1.0, 0.28);
+        p.input("p1_f",       0.0, 1.0, 0.138);
+        p.input("p1_g",       0.0, 1.0, 0.5);
+        p.input("p1_q",       0.0, 1.0, 0.5);
+        p.input("p2_f",       0.0, 1.0, 0.272);
+        p.input("p2_g",       0.0, 1.0, 0.5);
+        p.input("p2_q",       0.0, 1.0, 0.5);
+        p.input("p3_f",       0.0, 1.0, 0.5);
+        p.input("p3_g",       0.0, 1.0, 0.5);
+        p.input("p3_q",       0.0, 1.0, 0.5);
+        p.input("hs_f",       0.0, 1.0, 0.632);
+        p.input("hs_g",       0.0, 1.0, 0.5);
+        p.input("hs_q",       0.0, 1.0, 0.28);
+        p.input("hc_f",       0.0, 1.0, 1.0);
+        p.input("hc_q",       0.0, 1.0, 0.28);
+        p.input("out_g",      0.0, 1.0, 0.5);
+
+        let types = [
+            BiquadType::Highpass,
+            BiquadType::LowShelf,
+            BiquadType::Peak,
+            BiquadType::Peak,
+            BiquadType::Peak,
+            BiquadType::HighShelf,
+            BiquadType::Lowpass,
+        ];
+
+        let mut bands = Vec::with_capacity(NUM_BANDS);
+        let mut offs  = 0;
+        for t in types.iter() {
+            let band = LevellerBand::new(sample_rate, *t, offs);
+            offs += band.num_ports();
+            bands.push(band);
+        }
+
+        let mut l = Leveller {
+            params:      p,
+            bands,
+            output_gain: 1.0,
+        };
+        let v : Vec<f32> = (0..NUM_PORTS).map(|i| l.params.v(i)).collect();
+        l.set(&v);
+        l
+    }
+
+    fn set(&mut self, v: &[f32]) {
+        for band in self.bands.iter_mut() {
+            band.set(v);
+        }
+        self.output_gain =
+            helpers::db_to_scalar(helpers::param_to_db(v[v.len() - 1], GAIN_RANGE));
+    }
+
+    pub fn clear(&mut self) {
+        for band in self.bands.iter_mut() {
+            band.left.reset();
+            band.right.reset();
+        }
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            let mut l = frame[0];
+            let mut r = frame[1];
+            for band in self.bands.iter_mut() {
+                if !band.enabled { continue; }
+                l = band.left.next(l);
+                r = band.right.next(r);
+            }
+
+            frame[0] = l * self.output_gain;
+            frame[1] = r * self.output_gain;
+        }
+    }
+}
+
+impl Op for Leveller {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        let mut v = [0.0; NUM_PORTS];
+        for (i, v) in v.iter_mut().enumerate() {
+            *v = self.params.inputs[i].calc(regs);
+        }
+        self.set(&v);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_leveller(sample_rate: f64) -> Leveller {
+    Leveller::new(sample_rate)
+}
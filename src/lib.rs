@@ -1,12 +1,38 @@
 pub mod helpers;
 mod parameters;
 mod state_variable_filter;
+mod biquad;
 mod envelope;
 mod synth_device;
 mod sample_player;
-mod sample_loader;
+pub mod sample_loader;
+mod resampler;
 mod all_pass;
 mod all_pass_delay;
+mod comb;
+mod delay_buffer;
 pub mod slaughter;
+pub mod falcon;
+pub mod adultery;
+pub mod kentucky;
+pub mod thunder;
+pub mod echo;
+pub mod cathedral;
+pub mod twister;
+pub mod smasher;
+pub mod leveller;
+pub mod crusher;
+pub mod scissor;
 
 pub use slaughter::new_slaughter;
+pub use falcon::new_falcon;
+pub use adultery::new_adultery;
+pub use kentucky::new_kentucky;
+pub use thunder::new_thunder;
+pub use echo::new_echo;
+pub use cathedral::new_cathedral;
+pub use twister::new_twister;
+pub use smasher::new_smasher;
+pub use leveller::new_leveller;
+pub use crusher::new_crusher;
+pub use scissor::new_scissor;
@@ -5,6 +5,7 @@ mod envelope;
 mod synth_device;
 mod sample_player;
 mod sample_loader;
+mod resampler;
 mod all_pass;
 mod all_pass_delay;
 mod slaughter;
@@ -85,7 +86,8 @@ fn audio() {
 
         let channels = format.channels as usize;
 
-        let sample1 = sample_loader::load_wav("test_s1.wav");
+        let sample1 = sample_loader::load_wav("test_s1.wav")
+            .expect("Couldn't load 'test_s1.wav'");
         let lens = sample1.len();
         println!("LOADED SMAPLE {}", lens);
 
@@ -93,7 +95,13 @@ fn audio() {
         sp.loop_mode = sample_player::LoopMode::PingPong;
         sp.sample_loop_start = 0;
         sp.sample_loop_length = lens as i32;
-        sp.sample_data = sample1;
+        if let Some(l) = sample1.sample_loop {
+            sp.loop_mode          = l.mode;
+            sp.sample_loop_start  = l.start;
+            sp.sample_loop_length = l.length;
+        }
+        sp.sample_data = std::sync::Arc::new(sample1.to_mono());
+        sp.set_source_sample_rate(sample1.sample_rate as f64);
         sp.calc_pitch(0.0);
         sp.init_pos();
         sp.run_prep();
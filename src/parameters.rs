@@ -30,6 +30,41 @@ impl From<FilterType> for f32 {
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum BiquadType {
+    Lowpass,
+    Highpass,
+    LowShelf,
+    HighShelf,
+    Peak,
+}
+
+impl From<f32> for BiquadType {
+    fn from(item: f32) -> Self {
+        let i = (item * 4.0) as i32;
+        match i {
+            0 => BiquadType::Lowpass,
+            1 => BiquadType::Highpass,
+            2 => BiquadType::LowShelf,
+            3 => BiquadType::HighShelf,
+            4 => BiquadType::Peak,
+            _ => BiquadType::Lowpass,
+        }
+    }
+}
+
+impl From<BiquadType> for f32 {
+    fn from(item: BiquadType) -> f32 {
+        match item {
+            BiquadType::Lowpass   => 0.0,
+            BiquadType::Highpass  => 0.25,
+            BiquadType::LowShelf  => 0.5,
+            BiquadType::HighShelf => 0.75,
+            BiquadType::Peak      => 1.0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum VoiceMode {
     Polyphonic,
@@ -56,6 +91,40 @@ impl From<VoiceMode> for f32 {
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum StealMode {
+    Oldest,
+    Quietest,
+    SameNote,
+    LowNotePriority,
+    HighNotePriority,
+}
+
+impl From<f32> for StealMode {
+    fn from(item: f32) -> Self {
+        let i = (item * 4.0) as i32;
+        match i {
+            0 => StealMode::Oldest,
+            1 => StealMode::Quietest,
+            2 => StealMode::SameNote,
+            3 => StealMode::LowNotePriority,
+            4 => StealMode::HighNotePriority,
+            _ => StealMode::Oldest,
+        }
+    }
+}
+
+impl From<StealMode> for f32 {
+    fn from(item: StealMode) -> f32 {
+        match item {
+            StealMode::Oldest           => 0.0,
+            StealMode::Quietest         => 0.25,
+            StealMode::SameNote         => 0.5,
+            StealMode::LowNotePriority  => 0.75,
+            StealMode::HighNotePriority => 1.0,
+        }
+    }
+}
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum Spread {
@@ -86,6 +155,96 @@ impl From<Spread> for f32 {
     }
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum TwisterMode {
+    Phaser,
+    Flanger,
+    Chorus,
+}
+
+impl From<f32> for TwisterMode {
+    fn from(item: f32) -> Self {
+        let i = (item * 2.0) as i32;
+        match i {
+            0 => TwisterMode::Phaser,
+            1 => TwisterMode::Flanger,
+            2 => TwisterMode::Chorus,
+            _ => TwisterMode::Phaser,
+        }
+    }
+}
+
+impl From<TwisterMode> for f32 {
+    fn from(item: TwisterMode) -> f32 {
+        match item {
+            TwisterMode::Phaser  => 0.0,
+            TwisterMode::Flanger => 0.5,
+            TwisterMode::Chorus  => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum ShaperType {
+    Clip,
+    Fold,
+    Tanh,
+    Sine,
+}
+
+impl From<f32> for ShaperType {
+    fn from(item: f32) -> Self {
+        let i = (item * 3.0) as i32;
+        match i {
+            0 => ShaperType::Clip,
+            1 => ShaperType::Fold,
+            2 => ShaperType::Tanh,
+            3 => ShaperType::Sine,
+            _ => ShaperType::Clip,
+        }
+    }
+}
+
+impl From<ShaperType> for f32 {
+    fn from(item: ShaperType) -> f32 {
+        match item {
+            ShaperType::Clip => 0.0,
+            ShaperType::Fold => 1.0 / 3.0,
+            ShaperType::Tanh => 2.0 / 3.0,
+            ShaperType::Sine => 1.0,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub enum CrushMode {
+    Round,
+    Truncate,
+    MuLaw,
+}
+
+impl From<f32> for CrushMode {
+    fn from(item: f32) -> Self {
+        let i = (item * 2.0) as i32;
+        match i {
+            0 => CrushMode::Round,
+            1 => CrushMode::Truncate,
+            2 => CrushMode::MuLaw,
+            _ => CrushMode::Round,
+        }
+    }
+}
+
+impl From<CrushMode> for f32 {
+    fn from(item: CrushMode) -> f32 {
+        match item {
+            CrushMode::Round    => 0.0,
+            CrushMode::Truncate => 0.5,
+            CrushMode::MuLaw    => 1.0,
+        }
+    }
+}
+
 pub enum Parameter {
     StateVariableFilterType(FilterType),
     Freq(f32),
@@ -0,0 +1,141 @@
+// Zero crossings of the sinc on each side of the center tap.
+const HALF_WIDTH : usize = 16;
+const NUM_TAPS   : usize = HALF_WIDTH * 2;
+// Number of precomputed fractional positions between two samples.
+const NUM_PHASES : usize = 256;
+
+/// A band limited polyphase sinc resampler for converting whole
+/// samples to another sample rate. The filter table holds a Blackman
+/// windowed sinc for `NUM_PHASES` fractional positions, positions in
+/// between are linearly interpolated.
+pub struct Resampler {
+    // Input samples per output sample.
+    step:   f64,
+    // (NUM_PHASES + 1) * NUM_TAPS coefficients.
+    table:  Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(from_rate: f64, to_rate: f64) -> Self {
+        let step   = from_rate / to_rate;
+        // Lower the cutoff below the new Nyquist frequency when
+        // downsampling, leaving some room for the transition band:
+        let cutoff = (1.0 / step).min(1.0) * 0.95;
+
+        let mut table = Vec::with_capacity((NUM_PHASES + 1) * NUM_TAPS);
+        for phase in 0..=NUM_PHASES {
+            let fract = phase as f64 / NUM_PHASES as f64;
+
+            let mut coefs = [0.0; NUM_TAPS];
+            let mut sum   = 0.0;
+            for (i, c) in coefs.iter_mut().enumerate() {
+                let x = i as f64 - (HALF_WIDTH - 1) as f64 - fract;
+                let sinc =
+                    if x.abs() < 0.000001 {
+                        1.0
+                    } else {
+                        let px = std::f64::consts::PI * x * cutoff;
+                        px.sin() / px
+                    };
+                let w = (x / HALF_WIDTH as f64 + 1.0) * 0.5;
+                let window =
+                    if w <= 0.0 || w >= 1.0 {
+                        0.0
+                    } else {
+                        0.42
+                        - 0.5  * (2.0 * std::f64::consts::PI * w).cos()
+                        + 0.08 * (4.0 * std::f64::consts::PI * w).cos()
+                    };
+
+                *c   = sinc * window;
+                sum += *c;
+            }
+
+            // Normalize for unity gain at DC:
+            for c in coefs.iter() {
+                table.push((c / sum) as f32);
+            }
+        }
+
+        Resampler { step, table }
+    }
+
+    /// Number of output frames for `in_frames` input frames.
+    pub fn output_len(&self, in_frames: usize) -> usize {
+        (in_frames as f64 / self.step).ceil() as usize
+    }
+
+    /// Maps a position in the input to a position in the output.
+    pub fn map_pos(&self, pos: f64) -> f64 { pos / self.step }
+
+    /// Resamples interleaved data with `channels` channels.
+    pub fn process(&self, input: &[f32], channels: usize) -> Vec<f32> {
+        let channels   = channels.max(1);
+        let in_frames  = input.len() / channels;
+        let out_frames = self.output_len(in_frames);
+
+        let mut out = Vec::with_capacity(out_frames * channels);
+        for i in 0..out_frames {
+            let pos   = i as f64 * self.step;
+            let ipos  = pos.floor() as i64;
+            let phase = (pos - ipos as f64) * NUM_PHASES as f64;
+            let p     = (phase as usize).min(NUM_PHASES - 1);
+            let pf    = (phase - p as f64) as f32;
+
+            let c1 = &self.table[(p * NUM_TAPS)..((p + 1) * NUM_TAPS)];
+            let c2 = &self.table[((p + 1) * NUM_TAPS)..((p + 2) * NUM_TAPS)];
+
+            for ch in 0..channels {
+                let mut s = 0.0;
+                for k in 0..NUM_TAPS {
+                    let idx = ipos + k as i64 - (HALF_WIDTH - 1) as i64;
+                    if idx < 0 || idx >= in_frames as i64 { continue; }
+
+                    let c = c1[k] + (c2[k] - c1[k]) * pf;
+                    s += input[idx as usize * channels + ch] * c;
+                }
+                out.push(s);
+            }
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(freq: f64, rate: f64, len: usize) -> Vec<f32> {
+        (0..len)
+            .map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate).sin() as f32)
+            .collect()
+    }
+
+    #[test]
+    fn test_resample_sine() {
+        let r   = Resampler::new(48000.0, 44100.0);
+        let out = r.process(&sine(1000.0, 48000.0, 4800), 1);
+        assert_eq!(out.len(), 4410);
+
+        // Away from the edges the output matches the sine
+        // at the new sample rate:
+        let expected = sine(1000.0, 44100.0, 4410);
+        for i in 100..4300 {
+            assert!((out[i] - expected[i]).abs() < 0.001,
+                    "sample {}: {} != {}", i, out[i], expected[i]);
+        }
+    }
+
+    #[test]
+    fn test_resample_removes_aliasing() {
+        // A 20kHz tone is above the Nyquist frequency of 11.025kHz
+        // at a sample rate of 22.05kHz and has to be filtered out:
+        let r   = Resampler::new(96000.0, 22050.0);
+        let out = r.process(&sine(20000.0, 96000.0, 9600), 1);
+
+        let peak = out[100..(out.len() - 100)].iter()
+            .fold(0.0f32, |m, s| m.max(s.abs()));
+        assert!(peak < 0.01, "peak {}", peak);
+    }
+}
@@ -1,15 +1,363 @@
 use hound;
+use std::io::{Read, Seek, SeekFrom};
+use crate::sample_player::LoopMode;
+use crate::resampler::Resampler;
+
+/// A loop as stored in the `smpl` chunk of a WAV file.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SampleLoop {
+    pub start:  i32,
+    pub length: i32,
+    /// Forward loops map to `LoopMode::Repeat`, alternating loops to
+    /// `LoopMode::PingPong`. Backward loops are played as `Repeat`.
+    pub mode:   LoopMode,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    Io(std::io::Error),
+    Wav(hound::Error),
+    /// The sample format or bit depth can't be converted.
+    Unsupported(String),
+}
+
+impl std::fmt::Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LoadError::Io(e)          => write!(f, "I/O error: {}", e),
+            LoadError::Wav(e)         => write!(f, "WAV error: {}", e),
+            LoadError::Unsupported(s) => write!(f, "unsupported format: {}", s),
+        }
+    }
+}
+
+impl std::error::Error for LoadError { }
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> Self { LoadError::Io(e) }
+}
+
+impl From<hound::Error> for LoadError {
+    fn from(e: hound::Error) -> Self {
+        match e {
+            hound::Error::IoError(e) => LoadError::Io(e),
+            e                        => LoadError::Wav(e),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Interleaved samples in the range -1..1.
+    pub data:           Vec<f32>,
+    pub channels:       usize,
+    /// The sample rate of the data. `load_wav` keeps the sample rate
+    /// of the file, see `load_wav_resampled` and `resample`.
+    pub sample_rate:    u32,
+    pub sample_loop:    Option<SampleLoop>,
+    /// MIDI note that plays the sample at its original pitch.
+    pub root_note:      i32,
+    /// Fine tuning in cents.
+    pub fine_tune:      f32,
+    /// Gain in dB from the `inst` chunk.
+    pub gain:           f32,
+    pub low_note:       i32,
+    pub high_note:      i32,
+    pub low_velocity:   i32,
+    pub high_velocity:  i32,
+}
+
+impl Sample {
+    pub fn new(data: Vec<f32>, channels: usize, sample_rate: u32) -> Self {
+        Sample {
+            data,
+            channels:       channels.max(1),
+            sample_rate,
+            sample_loop:    None,
+            root_note:      60,
+            fine_tune:      0.0,
+            gain:           0.0,
+            low_note:       0,
+            high_note:      127,
+            low_velocity:   1,
+            high_velocity:  127,
+        }
+    }
+
+    /// Number of sample frames.
+    pub fn len(&self) -> usize { self.data.len() / self.channels }
+
+    pub fn is_empty(&self) -> bool { self.data.is_empty() }
+
+    /// Averages all channels into one.
+    pub fn to_mono(&self) -> Vec<f32> {
+        if self.channels == 1 {
+            return self.data.clone();
+        }
+
+        let scale = 1.0 / self.channels as f32;
+        self.data.chunks(self.channels)
+            .map(|frame| frame.iter().sum::<f32>() * scale)
+            .collect()
+    }
+
+    /// Returns the left and right channel. A mono sample is returned
+    /// on both sides, channels beyond the second are dropped.
+    pub fn split_stereo(&self) -> (Vec<f32>, Vec<f32>) {
+        if self.channels == 1 {
+            return (self.data.clone(), self.data.clone());
+        }
+
+        self.data.chunks(self.channels)
+            .filter(|frame| frame.len() == self.channels)
+            .map(|frame| (frame[0], frame[1]))
+            .unzip()
+    }
+
+    /// Converts the data to `sample_rate`, the loop points are
+    /// moved along.
+    pub fn resample(&mut self, sample_rate: u32) {
+        if sample_rate == self.sample_rate || sample_rate == 0 {
+            return;
+        }
+
+        let r = Resampler::new(self.sample_rate as f64, sample_rate as f64);
+        self.data = r.process(&self.data, self.channels);
+
+        if let Some(l) = &mut self.sample_loop {
+            let end  = r.map_pos((l.start + l.length) as f64).round() as i32;
+            l.start  = r.map_pos(l.start as f64).round() as i32;
+            l.length = (end - l.start).max(1);
+        }
+        self.sample_rate = sample_rate;
+    }
+
+    fn parse_smpl(&mut self, chunk: &[u8]) {
+        if chunk.len() < 36 { return; }
+
+        self.root_note = read_u32(chunk, 12) as i32;
+        // The pitch fraction is given in fractions of a semitone:
+        self.fine_tune =
+            (read_u32(chunk, 16) as f64 / 4294967296.0 * 100.0) as f32;
+
+        let num_loops = read_u32(chunk, 28) as usize;
+        if num_loops < 1 || chunk.len() < 36 + 24 { return; }
+
+        // Only the first loop is used:
+        let l     = &chunk[36..60];
+        let start = read_u32(l, 8) as i32;
+        let end   = read_u32(l, 12) as i32;
+        if end < start { return; }
+
+        self.sample_loop = Some(SampleLoop {
+            start,
+            // The end sample is part of the loop:
+            length: end - start + 1,
+            mode:
+                match read_u32(l, 4) {
+                    1 => LoopMode::PingPong,
+                    _ => LoopMode::Repeat,
+                },
+        });
+    }
+
+    fn parse_inst(&mut self, chunk: &[u8]) {
+        if chunk.len() < 7 { return; }
+
+        self.root_note     = chunk[0] as i32;
+        self.fine_tune     = chunk[1] as i8 as f32;
+        self.gain          = chunk[2] as i8 as f32;
+        self.low_note      = chunk[3] as i32;
+        self.high_note     = chunk[4] as i32;
+        self.low_velocity  = chunk[5] as i32;
+        self.high_velocity = chunk[6] as i32;
+    }
+}
+
+fn read_u32(b: &[u8], offs: usize) -> u32 {
+    u32::from_le_bytes([b[offs], b[offs + 1], b[offs + 2], b[offs + 3]])
+}
+
+// Walks the RIFF chunks of a WAV file and parses the `smpl` and
+// `inst` chunks, all other chunks are skipped. The `inst` chunk is
+// parsed last, as its root note and fine tune take precedence over
+// those of the `smpl` chunk.
+fn parse_chunks<R: Read + Seek>(r: &mut R, sample: &mut Sample) -> std::io::Result<()> {
+    let mut header = [0u8; 12];
+    r.seek(SeekFrom::Start(0))?;
+    r.read_exact(&mut header)?;
+    if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+        return Ok(());
+    }
+
+    let mut inst   = None;
+    let mut header = [0u8; 8];
+    while r.read_exact(&mut header).is_ok() {
+        let size = read_u32(&header, 4) as u64;
+
+        if &header[0..4] == b"smpl" || &header[0..4] == b"inst" {
+            let mut data = Vec::new();
+            r.by_ref().take(size).read_to_end(&mut data)?;
+
+            if &header[0..4] == b"smpl" {
+                sample.parse_smpl(&data);
+            } else {
+                inst = Some(data);
+            }
+        } else {
+            r.seek(SeekFrom::Current(size as i64))?;
+        }
+
+        // Chunks are padded to an even size:
+        if size & 1 == 1 {
+            r.seek(SeekFrom::Current(1))?;
+        }
+    }
+
+    if let Some(data) = inst {
+        sample.parse_inst(&data);
+    }
+    Ok(())
+}
+
+/// Loads 8, 16, 24 or 32 bit integer or 32 bit float WAV files.
+/// The samples are converted to f32, but keep their channel
+/// layout and sample rate.
+pub fn load_wav(file: &str) -> Result<Sample, LoadError> {
+    let mut reader = hound::WavReader::open(file)?;
+    let spec       = reader.spec();
 
-pub fn load_wav(file: &str) -> Vec<f32> {
-    let mut reader = hound::WavReader::open(file)
-        .expect(&format!("Couldn't open file '{}'", file));
-    // TODO: Add proper error reporting from WavSpec
-    // TODO: Make sample conversion from any format to 44.1khz in f32
-    // TODO: Resample different sample rates to the current sample rate
-    //       which should be passed into this function.
     let samples : Vec<f32> =
-        reader.samples::<f32>()
-            .map(|s| s.expect("sample files need to be 44.1khz float pcm"))
-            .collect();
-    samples
+        match (spec.sample_format, spec.bits_per_sample) {
+            (hound::SampleFormat::Float, 32) =>
+                reader.samples::<f32>().collect::<Result<_, _>>()?,
+            (hound::SampleFormat::Int, bits) if (8..=32).contains(&bits) => {
+                let scale = 1.0 / (1u64 << (bits - 1)) as f64;
+                reader.samples::<i32>()
+                    .map(|s| s.map(|s| (s as f64 * scale) as f32))
+                    .collect::<Result<_, _>>()?
+            },
+            (format, bits) =>
+                return Err(LoadError::Unsupported(
+                    format!("{} bit {:?}", bits, format))),
+        };
+
+    let mut sample =
+        Sample::new(samples, spec.channels as usize, spec.sample_rate);
+    parse_chunks(&mut reader.into_inner(), &mut sample)?;
+    Ok(sample)
+}
+
+/// Like `load_wav`, but converts the data to `sample_rate`.
+pub fn load_wav_resampled(file: &str, sample_rate: u32) -> Result<Sample, LoadError> {
+    let mut sample = load_wav(file)?;
+    sample.resample(sample_rate);
+    Ok(sample)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(id: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut c = id.to_vec();
+        c.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        c.extend_from_slice(data);
+        if data.len() & 1 == 1 { c.push(0); }
+        c
+    }
+
+    #[test]
+    fn test_parse_smpl_and_inst() {
+        let mut smpl = vec![0u8; 36 + 24];
+        smpl[12..16].copy_from_slice(&57u32.to_le_bytes());
+        smpl[28..32].copy_from_slice(&1u32.to_le_bytes());
+        smpl[40..44].copy_from_slice(&1u32.to_le_bytes());
+        smpl[44..48].copy_from_slice(&100u32.to_le_bytes());
+        smpl[48..52].copy_from_slice(&199u32.to_le_bytes());
+
+        let inst = [62u8, (-10i8) as u8, 3, 40, 80, 1, 127];
+
+        let mut bytes = b"RIFF\0\0\0\0WAVE".to_vec();
+        bytes.extend(chunk(b"fmt ", &[0; 16]));
+        bytes.extend(chunk(b"inst", &inst));
+        bytes.extend(chunk(b"smpl", &smpl));
+
+        let mut s = Sample::new(vec![], 1, 44100);
+        parse_chunks(&mut std::io::Cursor::new(bytes), &mut s).unwrap();
+
+        assert_eq!(s.sample_loop, Some(SampleLoop {
+            start: 100, length: 100, mode: LoopMode::PingPong }));
+        assert_eq!(s.root_note, 62);
+        assert_eq!(s.fine_tune, -10.0);
+        assert_eq!(s.gain,      3.0);
+        assert_eq!(s.low_note,  40);
+        assert_eq!(s.high_note, 80);
+    }
+
+    #[test]
+    fn test_load_16bit_stereo() {
+        let path = std::env::temp_dir().join("sample_loader_16bit_stereo.wav");
+        let spec = hound::WavSpec {
+            channels:        2,
+            sample_rate:     22050,
+            bits_per_sample: 16,
+            sample_format:   hound::SampleFormat::Int,
+        };
+        let mut w = hound::WavWriter::create(&path, spec).unwrap();
+        for s in [16384i16, -32768, 0, 8192].iter() {
+            w.write_sample(*s).unwrap();
+        }
+        w.finalize().unwrap();
+
+        let s = load_wav(path.to_str().unwrap()).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(s.channels,    2);
+        assert_eq!(s.sample_rate, 22050);
+        assert_eq!(s.len(),       2);
+        assert_eq!(s.split_stereo(), (vec![0.5, 0.0], vec![-1.0, 0.25]));
+        assert_eq!(s.to_mono(),      vec![-0.25, 0.125]);
+
+        assert!(matches!(load_wav("does_not_exist.wav"), Err(LoadError::Io(_))));
+    }
+
+    #[test]
+    fn test_load_resampled() {
+        let path = std::env::temp_dir().join("sample_loader_resampled.wav");
+        let spec = hound::WavSpec {
+            channels:        1,
+            sample_rate:     22050,
+            bits_per_sample: 16,
+            sample_format:   hound::SampleFormat::Int,
+        };
+        let mut w = hound::WavWriter::create(&path, spec).unwrap();
+        for _ in 0..100 {
+            w.write_sample(16384i16).unwrap();
+        }
+        w.finalize().unwrap();
+
+        let s = load_wav_resampled(path.to_str().unwrap(), 44100).unwrap();
+        std::fs::remove_file(&path).ok();
+
+        assert_eq!(s.sample_rate, 44100);
+        assert_eq!(s.len(),       200);
+        assert!((s.data[100] - 0.5).abs() < 0.001, "{}", s.data[100]);
+    }
+
+    #[test]
+    fn test_resample_loop() {
+        let mut s = Sample::new(vec![0.0; 2 * 1000], 2, 48000);
+        s.sample_loop = Some(SampleLoop {
+            start: 100, length: 200, mode: LoopMode::Repeat });
+
+        s.resample(48000);
+        assert_eq!(s.len(), 1000);
+
+        s.resample(24000);
+        assert_eq!(s.sample_rate, 24000);
+        assert_eq!(s.len(),       500);
+        assert_eq!(s.sample_loop, Some(SampleLoop {
+            start: 50, length: 100, mode: LoopMode::Repeat }));
+    }
 }
@@ -1,9 +1,92 @@
 use crate::helpers;
+use std::sync::{Arc, OnceLock};
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum InterpolationMode {
     Nearest,
     Linear,
+    Hermite,
+    Sinc,
+}
+
+impl From<f32> for InterpolationMode {
+    fn from(item: f32) -> Self {
+        let i = (item * 3.0) as i32;
+        match i {
+            0 => InterpolationMode::Nearest,
+            1 => InterpolationMode::Linear,
+            2 => InterpolationMode::Hermite,
+            3 => InterpolationMode::Sinc,
+            _ => InterpolationMode::Nearest,
+        }
+    }
+}
+
+impl From<InterpolationMode> for f32 {
+    fn from(item: InterpolationMode) -> f32 {
+        match item {
+            InterpolationMode::Nearest => 0.0,
+            InterpolationMode::Linear  => 1.0 / 3.0,
+            InterpolationMode::Hermite => 2.0 / 3.0,
+            InterpolationMode::Sinc    => 1.0,
+        }
+    }
+}
+
+// Number of samples on each side of the read position
+// that are used by the windowed sinc interpolation.
+const SINC_HALF_WIDTH : i32   = 8;
+// Number of precomputed points per sample in the sinc tables.
+const SINC_TABLE_RES  : usize = 512;
+
+// The sinc and the Blackman window over the distance from the read
+// position, from 0 to SINC_HALF_WIDTH. Both are symmetric, and the
+// sinc is tabulated separately from the window so that its cutoff
+// can follow the playback speed.
+struct SincTable {
+    sinc:   Vec<f32>,
+    window: Vec<f32>,
+}
+
+impl SincTable {
+    fn new() -> Self {
+        let len   = SINC_HALF_WIDTH as usize * SINC_TABLE_RES + 2;
+        let width = SINC_HALF_WIDTH as f64;
+
+        let mut sinc   = Vec::with_capacity(len);
+        let mut window = Vec::with_capacity(len);
+        for i in 0..len {
+            let x = i as f64 / SINC_TABLE_RES as f64;
+            sinc.push(
+                if x < 0.000001 {
+                    1.0
+                } else {
+                    let px = std::f64::consts::PI * x;
+                    (px.sin() / px) as f32
+                });
+
+            let w = (x.min(width) / width + 1.0) * 0.5;
+            window.push(
+                (0.42
+                 - 0.5  * (2.0 * std::f64::consts::PI * w).cos()
+                 + 0.08 * (4.0 * std::f64::consts::PI * w).cos()) as f32);
+        }
+
+        SincTable { sinc, window }
+    }
+
+    fn lookup(tab: &[f32], x: f64) -> f64 {
+        let p     = x * SINC_TABLE_RES as f64;
+        let i     = (p as usize).min(tab.len() - 2);
+        let fract = p - i as f64;
+        tab[i] as f64 + (tab[i + 1] - tab[i]) as f64 * fract
+    }
+}
+
+// The table is shared by all players and built on first use.
+fn sinc_table() -> &'static SincTable {
+    static TABLE : OnceLock<SincTable> = OnceLock::new();
+    TABLE.get_or_init(SincTable::new)
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -13,12 +96,54 @@ pub enum LoopMode {
     PingPong,
 }
 
+impl From<f32> for LoopMode {
+    fn from(item: f32) -> Self {
+        let i = (item * 2.0) as i32;
+        match i {
+            0 => LoopMode::Disabled,
+            1 => LoopMode::Repeat,
+            2 => LoopMode::PingPong,
+            _ => LoopMode::Disabled,
+        }
+    }
+}
+
+impl From<LoopMode> for f32 {
+    fn from(item: LoopMode) -> f32 {
+        match item {
+            LoopMode::Disabled => 0.0,
+            LoopMode::Repeat   => 0.5,
+            LoopMode::PingPong => 1.0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum LoopBoundaryMode {
     FromSample,
     Manual,
 }
 
+impl From<f32> for LoopBoundaryMode {
+    fn from(item: f32) -> Self {
+        let i = item as i32;
+        match i {
+            0 => LoopBoundaryMode::FromSample,
+            1 => LoopBoundaryMode::Manual,
+            _ => LoopBoundaryMode::FromSample,
+        }
+    }
+}
+
+impl From<LoopBoundaryMode> for f32 {
+    fn from(item: LoopBoundaryMode) -> f32 {
+        match item {
+            LoopBoundaryMode::FromSample => 0.0,
+            LoopBoundaryMode::Manual     => 1.0,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct SamplePlayer {
     pub sample_rate:        f64,
@@ -30,19 +155,30 @@ pub struct SamplePlayer {
     pub loop_start:         f32,
     pub loop_length:        f32,
     pub interpolation_mode: InterpolationMode,
-    pub sample_data:        Vec<f32>,
+    pub sample_data:        Arc<Vec<f32>>,
     pub sample_loop_start:  i32,
     pub sample_loop_length: i32,
+    /// Length of the loop crossfade as fraction of the loop length.
+    pub loop_crossfade:     f32,
+    /// Sample rate of the sample data divided by the playback
+    /// sample rate, see `set_source_sample_rate`.
+    pub sample_rate_ratio:  f64,
         sample_pos:         f64,
         sample_delta:       f64,
         rounded_loop_start: i32,
         rounded_loop_len:   i32,
         rounded_loop_end:   i32,
+        xfade_start_len:    f64,
+        xfade_end_len:      f64,
         reverse_:           bool,
+        has_looped:         bool,
 }
 
 impl SamplePlayer {
     pub fn new(sample_rate: f64) -> Self {
+        // Build the sinc table here instead of on the audio thread:
+        sinc_table();
+
         SamplePlayer {
             sample_rate,
             reverse:            false,
@@ -52,21 +188,34 @@ impl SamplePlayer {
             loop_length:        1.0,
             sample_loop_start:  0,
             sample_loop_length: 0,
+            loop_crossfade:     0.0,
+            sample_rate_ratio:  1.0,
             rounded_loop_start: 0,
             rounded_loop_len:   0,
             rounded_loop_end:   0,
+            xfade_start_len:    0.0,
+            xfade_end_len:      0.0,
             sample_delta:       0.0,
             sample_pos:         0.0,
             sample_start:       0.0,
             reverse_:           false,
+            has_looped:         false,
             interpolation_mode: InterpolationMode::Linear,
-            sample_data:        Vec::new(),
+            sample_data:        Arc::new(Vec::new()),
             is_active:          false,
         }
     }
 
+    /// Sets the sample rate the sample data was recorded at, so that
+    /// it plays at its original pitch without being resampled.
+    /// 0.0 means the data has the playback sample rate.
+    pub fn set_source_sample_rate(&mut self, rate: f64) {
+        self.sample_rate_ratio =
+            if rate > 0.0 { rate / self.sample_rate } else { 1.0 };
+    }
+
     pub fn calc_pitch(&mut self, note: f64) {
-        let freq_delta = helpers::pow(2.0, note / 12.0);
+        let freq_delta = helpers::pow(2.0, note / 12.0) * self.sample_rate_ratio;
         self.sample_delta =
             if !self.reverse_ { freq_delta } else { -freq_delta };
     }
@@ -74,6 +223,7 @@ impl SamplePlayer {
     pub fn init_pos(&mut self) {
         self.reverse_ = self.reverse;
         self.is_active = true;
+        self.has_looped = false;
         self.sample_pos =
             if !self.reverse_ {
                 self.sample_start as f64
@@ -116,45 +266,195 @@ impl SamplePlayer {
             self.rounded_loop_end = self.sample_data.len() as i32;
             self.rounded_loop_len = self.rounded_loop_end - self.rounded_loop_start;
         }
+
+        // The crossfades blend in the samples in front of the loop start
+        // and behind the loop end, so they are limited by how much
+        // sample data is available there:
+        let xfade =
+            helpers::clamp(self.loop_crossfade, 0.0, 1.0) as f64
+            * self.rounded_loop_len as f64;
+        let pre_loop  = self.rounded_loop_start as f64;
+        let post_loop = (self.sample_data.len() as i32 - self.rounded_loop_end) as f64;
+        let half_loop = self.rounded_loop_len as f64 * 0.5;
+
+        match self.loop_mode {
+            LoopMode::Repeat => {
+                self.xfade_start_len = 0.0;
+                self.xfade_end_len   = xfade.min(pre_loop);
+            },
+            LoopMode::PingPong => {
+                self.xfade_start_len = xfade.min(half_loop).min(pre_loop);
+                self.xfade_end_len   = xfade.min(half_loop).min(post_loop.max(0.0));
+            },
+            LoopMode::Disabled => {
+                self.xfade_start_len = 0.0;
+                self.xfade_end_len   = 0.0;
+            },
+        }
     }
 
-    pub fn next(&mut self) -> f32 {
-        let sample_len = self.sample_data.len() as i32;
-        let sample_pos_floor = self.sample_pos.floor();
-        let sample_pos_fract = self.sample_pos - sample_pos_floor;
+    // Maps the index of the sample `offs` samples away from `pos` into
+    // the loop, so that the interpolation sees the samples that are
+    // played next (or were played before) instead of those behind
+    // the loop boundaries.
+    fn loop_index(&self, pos: i32, offs: i32) -> i32 {
+        let start   = self.rounded_loop_start;
+        let end     = self.rounded_loop_end;
+        let mut idx = pos + offs;
+        if end - start < 1 || pos < start || pos >= end { return idx; }
+
+        match self.loop_mode {
+            LoopMode::Repeat => {
+                let len = end - start;
+                while idx >= end { idx -= len; }
+                if self.has_looped || self.sample_delta < 0.0 {
+                    while idx < start { idx += len; }
+                }
+            },
+            LoopMode::PingPong => {
+                // Mirroring at both boundaries repeats every two
+                // loop lengths, fold the index into that period
+                // and mirror the second half back:
+                if idx >= end || (idx < start && self.has_looped) {
+                    let len = end - start;
+                    let m   = (idx - start).rem_euclid(2 * len);
+                    idx =
+                        if m < len { start + m }
+                        else       { start + 2 * len - 1 - m };
+                }
+            },
+            LoopMode::Disabled => (),
+        }
+
+        idx
+    }
+
+    fn sample_at(&self, pos: i32, offs: i32) -> f32 {
+        let idx = self.loop_index(pos, offs);
+        if idx < 0 || idx >= self.sample_data.len() as i32 {
+            0.0
+        } else {
+            self.sample_data[idx as usize]
+        }
+    }
+
+    // Blackman windowed sinc interpolation. When the sample is pitched
+    // up the cutoff is lowered to the new nyquist frequency.
+    fn sinc_interpolate(&self, pos: i32, fract: f64) -> f32 {
+        let cutoff = (1.0 / self.sample_delta.abs().max(1.0)).min(1.0);
+        let table  = sinc_table();
+
+        let mut sum    = 0.0;
+        let mut weight = 0.0;
+        for k in (1 - SINC_HALF_WIDTH)..=SINC_HALF_WIDTH {
+            let x = (k as f64 - fract).abs();
+            let c =
+                SincTable::lookup(&table.sinc, x * cutoff)
+                * SincTable::lookup(&table.window, x);
+            sum    += self.sample_at(pos, k) as f64 * c;
+            weight += c;
+        }
+
+        if weight.abs() > 0.000001 { (sum / weight) as f32 } else { 0.0 }
+    }
+
+    // Reads the interpolated sample at `pos`.
+    fn read(&self, pos: f64) -> f32 {
+        let sample_pos_floor = pos.floor();
+        let sample_pos_fract = pos - sample_pos_floor;
 
         let rounded_sample_pos = sample_pos_floor as i32;
-        if rounded_sample_pos < 0 || rounded_sample_pos >= sample_len {
+        if rounded_sample_pos < 0
+           || rounded_sample_pos >= self.sample_data.len() as i32 {
+            return 0.0;
+        }
+
+        match self.interpolation_mode {
+            InterpolationMode::Nearest => {
+                self.sample_data[rounded_sample_pos as usize]
+            },
+            InterpolationMode::Linear => {
+                let left  = self.sample_data[rounded_sample_pos as usize];
+                let right = self.sample_at(rounded_sample_pos, 1);
+
+                (   left as f64 * (1.0 - sample_pos_fract)
+                 + right as f64 * sample_pos_fract)
+                as f32
+            },
+            InterpolationMode::Hermite => {
+                let xm1 = self.sample_at(rounded_sample_pos, -1) as f64;
+                let x0  = self.sample_data[rounded_sample_pos as usize] as f64;
+                let x1  = self.sample_at(rounded_sample_pos, 1) as f64;
+                let x2  = self.sample_at(rounded_sample_pos, 2) as f64;
+
+                let c1 = 0.5 * (x1 - xm1);
+                let c2 = xm1 - 2.5 * x0 + 2.0 * x1 - 0.5 * x2;
+                let c3 = 0.5 * (x2 - xm1) + 1.5 * (x0 - x1);
+
+                (((c3 * sample_pos_fract + c2) * sample_pos_fract + c1)
+                 * sample_pos_fract + x0) as f32
+            },
+            InterpolationMode::Sinc => {
+                self.sinc_interpolate(rounded_sample_pos, sample_pos_fract)
+            },
+        }
+    }
+
+    // Reads the sample at `pos` with the loop crossfades applied.
+    // For Repeat the loop tail is blended into the samples in front of
+    // the loop start, so the end joins seamlessly with the start. For
+    // PingPong the samples around a turnaround are blended with their
+    // mirror image on the other side of the loop boundary.
+    fn read_crossfaded(&self, pos: f64) -> f32 {
+        let start = self.rounded_loop_start as f64;
+        let end   = self.rounded_loop_end as f64;
+
+        match self.loop_mode {
+            LoopMode::Repeat if self.xfade_end_len > 0.0 => {
+                let xf_start = end - self.xfade_end_len;
+                if pos >= xf_start && pos < end {
+                    let t = ((pos - xf_start) / self.xfade_end_len) as f32;
+                    return helpers::mix(
+                        self.read(pos),
+                        self.read(pos - self.rounded_loop_len as f64),
+                        t);
+                }
+            },
+            LoopMode::PingPong => {
+                let turn_end = end - 1.0;
+                if self.xfade_end_len > 0.0
+                   && pos > turn_end - self.xfade_end_len && pos <= turn_end {
+
+                    let k = turn_end - pos;
+                    let w = (0.5 * (1.0 - k / self.xfade_end_len)) as f32;
+                    return helpers::mix(
+                        self.read(pos), self.read(turn_end + k), w);
+                }
+
+                if self.xfade_start_len > 0.0 && self.has_looped
+                   && pos >= start && pos < start + self.xfade_start_len {
+
+                    let k = pos - start;
+                    let w = (0.5 * (1.0 - k / self.xfade_start_len)) as f32;
+                    return helpers::mix(
+                        self.read(pos), self.read(start - k), w);
+                }
+            },
+            _ => (),
+        }
+
+        self.read(pos)
+    }
+
+    pub fn next(&mut self) -> f32 {
+        let rounded_sample_pos = self.sample_pos.floor() as i32;
+        if rounded_sample_pos < 0
+           || rounded_sample_pos >= self.sample_data.len() as i32 {
             self.is_active = false;
             return 0.0;
         }
 
-        let mut sample : f32 =
-            match self.interpolation_mode {
-                InterpolationMode::Nearest => {
-                    self.sample_data[rounded_sample_pos as usize]
-                },
-                InterpolationMode::Linear => {
-                    let left = self.sample_data[rounded_sample_pos as usize];
-                    let right_index =
-                        if self.loop_mode == LoopMode::Repeat
-                           && (rounded_sample_pos + 1) == self.rounded_loop_end {
-                            self.rounded_loop_start
-                        } else {
-                            rounded_sample_pos + 1
-                        };
-                    let right =
-                        if right_index < sample_len {
-                            self.sample_data[right_index as usize]
-                        } else {
-                            0.0
-                        };
-
-                    (   left as f64 * (1.0 - sample_pos_fract)
-                     + right as f64 * sample_pos_fract)
-                    as f32
-                },
-            };
+        let sample = self.read_crossfaded(self.sample_pos);
 
         self.sample_pos += self.sample_delta;
 
@@ -163,10 +463,12 @@ impl SamplePlayer {
                 if self.sample_delta > 0.0 {
                     while self.sample_pos >= self.rounded_loop_end as f64 {
                         self.sample_pos -= self.rounded_loop_len as f64;
+                        self.has_looped = true;
                     }
                 } else {
-                    while self.sample_pos < self.rounded_loop_end as f64 {
+                    while self.sample_pos < self.rounded_loop_start as f64 {
                         self.sample_pos += self.rounded_loop_len as f64;
+                        self.has_looped = true;
                     }
                 }
             },
@@ -177,6 +479,7 @@ impl SamplePlayer {
                     self.sample_pos = (self.rounded_loop_end - 1) as f64;
                     self.sample_delta = -self.sample_delta;
                     self.reverse_ = !self.reverse_;
+                    self.has_looped = true;
 
                 } else if self.sample_delta < 0.0
                           && self.sample_pos < self.rounded_loop_start as f64 {
@@ -184,7 +487,7 @@ impl SamplePlayer {
                     self.sample_pos = self.rounded_loop_start as f64;
                     self.sample_delta = -self.sample_delta;
                     self.reverse_ = !self.reverse_;
-                    self.rounded_loop_start as f64;
+                    self.has_looped = true;
                 }
             },
             LoopMode::Disabled => (),
@@ -193,3 +496,124 @@ impl SamplePlayer {
         sample
      }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays a looped sine with a whole number of periods in the loop
+    // at half speed, the interpolated output must stay on the sine
+    // across the loop boundary. The first samples are skipped, there
+    // are no neighbours before the sample start.
+    fn check_loop_interpolation(mode: InterpolationMode, max_err: f64) {
+        let period = 64.0;
+        let data : Vec<f32> =
+            (0..256)
+            .map(|i| (2.0 * std::f64::consts::PI * i as f64 / period).sin() as f32)
+            .collect();
+
+        let mut sp = SamplePlayer::new(44100.0);
+        sp.sample_data        = Arc::new(data);
+        sp.loop_mode          = LoopMode::Repeat;
+        sp.loop_boundary_mode = LoopBoundaryMode::FromSample;
+        sp.sample_loop_start  = 0;
+        sp.sample_loop_length = 256;
+        sp.interpolation_mode = mode;
+        sp.run_prep();
+        sp.calc_pitch(-12.0);
+        sp.init_pos();
+
+        for i in 0..1024 {
+            let expected =
+                (2.0 * std::f64::consts::PI * (i as f64 * 0.5) / period).sin();
+            let s = sp.next() as f64;
+            if i < 32 { continue; }
+            assert!((s - expected).abs() < max_err,
+                    "{:?} sample {}: {} != {}", mode, i, s, expected);
+        }
+    }
+
+    #[test]
+    fn test_interpolation_across_loop() {
+        check_loop_interpolation(InterpolationMode::Linear,  0.01);
+        check_loop_interpolation(InterpolationMode::Hermite, 0.001);
+        check_loop_interpolation(InterpolationMode::Sinc,    0.001);
+    }
+
+    #[test]
+    fn test_loop_crossfade() {
+        // A ramp jumps from 1.0 back to 0.5 at the loop wrap:
+        let data : Vec<f32> = (0..1000).map(|i| i as f32 / 1000.0).collect();
+
+        let mut sp = SamplePlayer::new(44100.0);
+        sp.sample_data        = Arc::new(data);
+        sp.loop_mode          = LoopMode::Repeat;
+        sp.loop_boundary_mode = LoopBoundaryMode::FromSample;
+        sp.sample_loop_start  = 500;
+        sp.sample_loop_length = 500;
+        sp.loop_crossfade     = 0.2;
+        sp.run_prep();
+        sp.calc_pitch(0.0);
+        sp.init_pos();
+
+        let mut last = sp.next();
+        for _ in 0..3000 {
+            let s = sp.next();
+            assert!((s - last).abs() < 0.01, "jump from {} to {}", last, s);
+            last = s;
+        }
+    }
+
+    #[test]
+    fn test_short_ping_pong_loop() {
+        let data : Vec<f32> = (0..100).map(|i| i as f32).collect();
+
+        let mut sp = SamplePlayer::new(44100.0);
+        sp.sample_data        = Arc::new(data);
+        sp.loop_mode          = LoopMode::PingPong;
+        sp.loop_boundary_mode = LoopBoundaryMode::FromSample;
+        sp.sample_loop_start  = 10;
+        sp.sample_loop_length = 3;
+        sp.run_prep();
+        sp.has_looped = true;
+
+        // A loop shorter than the sinc reaches is mirrored
+        // back and forth: 10 11 12 12 11 10 10 11 ...
+        let expected = [12, 11, 10, 10, 11, 12, 12, 11, 10, 10, 11, 12];
+        for (i, offs) in (-4..8).enumerate() {
+            assert_eq!(sp.loop_index(11, offs), expected[i], "offs {}", offs);
+        }
+        for offs in (1 - SINC_HALF_WIDTH)..=SINC_HALF_WIDTH {
+            let idx = sp.loop_index(12, offs);
+            assert!((10..13).contains(&idx), "offs {}: {}", offs, idx);
+        }
+    }
+
+    #[test]
+    fn test_source_sample_rate() {
+        let mut sp = SamplePlayer::new(44100.0);
+        sp.calc_pitch(0.0);
+        assert_eq!(sp.sample_delta, 1.0);
+
+        // Data at half the playback rate advances at half speed:
+        sp.set_source_sample_rate(22050.0);
+        sp.calc_pitch(0.0);
+        assert_eq!(sp.sample_delta, 0.5);
+        sp.calc_pitch(12.0);
+        assert_eq!(sp.sample_delta, 1.0);
+
+        sp.set_source_sample_rate(0.0);
+        sp.calc_pitch(0.0);
+        assert_eq!(sp.sample_delta, 1.0);
+    }
+
+    #[test]
+    fn test_interpolation_mode_conversion() {
+        for m in [InterpolationMode::Nearest, InterpolationMode::Linear,
+                  InterpolationMode::Hermite, InterpolationMode::Sinc].iter() {
+            let f : f32 = (*m).into();
+            let back : InterpolationMode = f.into();
+            assert_eq!(back, *m);
+        }
+    }
+}
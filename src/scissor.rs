@@ -0,0 +1,182 @@
+use crate::parameters::*;
+use crate::biquad::Biquad;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Largest drive in dB.
+const MAX_DRIVE      : f32   = 36.0;
+const MAX_OVERSAMPLE : usize = 8;
+const NUM_PORTS      : usize = 6;
+
+fn shape(shaper: ShaperType, x: f32) -> f32 {
+    match shaper {
+        ShaperType::Clip => helpers::clamp(x, -1.0, 1.0),
+        ShaperType::Fold => {
+            // Reflects the signal at -1 and 1 until it is in range:
+            let t = (x + 1.0) * 0.25;
+            let t = t - t.floor();
+            1.0 - (t * 4.0 - 2.0).abs()
+        },
+        ShaperType::Tanh => x.tanh(),
+        ShaperType::Sine =>
+            helpers::fast_sin(x as f64 * std::f64::consts::PI * 0.5) as f32,
+    }
+}
+
+// The anti aliasing lowpass in front of the decimation,
+// two cascaded biquads running at the oversampled rate.
+#[derive(Debug, Clone, Copy)]
+struct ScissorChannel {
+    last:   f32,
+    lp1:    Biquad,
+    lp2:    Biquad,
+}
+
+impl ScissorChannel {
+    fn new(sample_rate: f64, oversample: usize) -> Self {
+        let mut lp = Biquad::new(sample_rate * oversample as f64);
+        lp.set_type(BiquadType::Lowpass);
+        lp.set_freq((sample_rate * 0.45) as f32);
+        lp.set_q(0.707);
+
+        ScissorChannel { last: 0.0, lp1: lp, lp2: lp }
+    }
+
+    fn next(&mut self, shaper: ShaperType, oversample: usize,
+            drive: f32, input: f32) -> f32 {
+
+        if oversample == 1 {
+            return shape(shaper, input * drive);
+        }
+
+        // Linear interpolation between the input samples as upsampling:
+        let mut out = 0.0;
+        for i in 1..=oversample {
+            let x = self.last + (input - self.last) * (i as f32 / oversample as f32);
+            out = self.lp2.next(self.lp1.next(shape(shaper, x * drive)));
+        }
+        self.last = input;
+        out
+    }
+}
+
+/// Waveshaping distortion with selectable curves, drive
+/// and oversampling.
+pub struct Scissor {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    shaper:         ShaperType,
+    drive:          f32,
+    oversample:     usize,
+    dry:            f32,
+    wet:            f32,
+    output_gain:    f32,
+    left:           ScissorChannel,
+    right:          ScissorChannel,
+}
+
+impl Scissor {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("shaper",     0.0, 1.0, 0.0);
+        p.input("drive",      0.0, 1.0, 0.25);
+        p.input("os",         0.0, 1.0, 0.0);
+        p.input("dry",        0.0, 1.0, 0.0);
+        p.input("wet",        0.0, 1.0, 1.0);
+        p.input("out_g",      0.0, 1.0, 0.5);
+
+        let mut s = Scissor {
+            sample_rate,
+            shaper:      ShaperType::Clip,
+            drive:       1.0,
+            oversample:  1,
+            dry:         0.0,
+            wet:         0.0,
+            output_gain: 1.0,
+            left:        ScissorChannel::new(sample_rate, 1),
+            right:       ScissorChannel::new(sample_rate, 1),
+
+            params:      p,
+        };
+        let v : Vec<f32> = (0..NUM_PORTS).map(|i| s.params.v(i)).collect();
+        s.set(&v);
+        s
+    }
+
+    fn set(&mut self, v: &[f32]) {
+        self.shaper      = v[0].into();
+        self.drive       = helpers::db_to_scalar(helpers::clamp(v[1], 0.0, 1.0) * MAX_DRIVE);
+
+        // 1, 2, 4 or 8 times oversampling:
+        let oversample =
+            (1 << (helpers::clamp(v[2], 0.0, 1.0) * 3.0).round() as usize)
+            .min(MAX_OVERSAMPLE);
+        if oversample != self.oversample {
+            self.oversample = oversample;
+            self.left  = ScissorChannel::new(self.sample_rate, oversample);
+            self.right = ScissorChannel::new(self.sample_rate, oversample);
+        }
+
+        self.dry         = v[3];
+        self.wet         = v[4];
+        self.output_gain = helpers::db_to_scalar(helpers::param_to_db(v[5], 18.0));
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        let wet = self.wet * self.output_gain;
+
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            let l = self.left.next(self.shaper, self.oversample, self.drive, frame[0]);
+            let r = self.right.next(self.shaper, self.oversample, self.drive, frame[1]);
+
+            frame[0] = frame[0] * self.dry + l * wet;
+            frame[1] = frame[1] * self.dry + r * wet;
+        }
+    }
+}
+
+impl Op for Scissor {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        let mut v = [0.0; NUM_PORTS];
+        for (i, v) in v.iter_mut().enumerate() {
+            *v = self.params.inputs[i].calc(regs);
+        }
+        self.set(&v);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_scissor(sample_rate: f64) -> Scissor {
+    Scissor::new(sample_rate)
+}
@@ -67,14 +67,16 @@ pub struct SlaughterParams {
     pitch_sustain:          f32,
     pitch_release:          f32,
     pitch_env_amt:          f32,
+    vel_amp:                f32,
+    vel_filter:             f32,
+    vel_env:                f32,
+    pressure_filter:        f32,
 }
 
 impl SlaughterParams {
     pub fn new() -> Self {
         let mut p = SignalIOParams::new();
 
-        // TODO: init SynthDeviceParams
-
         p.input("o1_vol",     0.0, 1.0, 1.0);
         p.input("o2_vol",     0.0, 1.0, 1.0);
         p.input("o3_vol",     0.0, 1.0, 1.0);
@@ -107,10 +109,16 @@ impl SlaughterParams {
         p.input("pit_d",      0.0, 1.0, 5.0);
         p.input("pit_s",      0.0, 1.0, 0.5);
         p.input("pit_r",      0.0, 1.0, 1.5);
-        p.input("pit_eamt",   0.0, 1.0, 0.0);
+        p.input("pit_eamt",   0.0, 1.0, 0.5);
+        p.input("vel_amp",    0.0, 1.0, 1.0);
+        p.input("vel_flt",    0.0, 1.0, 0.5);
+        p.input("vel_env",    0.0, 1.0, 0.0);
+        p.input("prs_flt",    0.0, 1.0, 0.5);
+
+        let dev_params = SynthDeviceParams::new_with_params(&mut p);
 
         SlaughterParams {
-            // TODO: Assign SynthDeviceParams!
+            dev_params,
             osc1_volume:        p.v(0),
             osc2_volume:        p.v(1),
             osc3_volume:        p.v(2),
@@ -144,12 +152,67 @@ impl SlaughterParams {
             pitch_sustain:      p.v(30),
             pitch_release:      p.v(31),
             pitch_env_amt:      p.v(32),
+            vel_amp:            p.v(33),
+            vel_filter:         p.v(34),
+            vel_env:            p.v(35),
+            pressure_filter:    p.v(36),
 
             params:             p,
         }
     }
 }
 
+impl AsMut<SignalIOParams> for SlaughterParams {
+    fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+}
+
+impl VoiceParams for SlaughterParams {
+    fn update(&mut self) -> SynthDeviceParams {
+        let v = &self.params.values;
+
+        self.osc1_volume        = v[0];
+        self.osc2_volume        = v[1];
+        self.osc3_volume        = v[2];
+        self.noise_volume       = v[3];
+        self.osc1_waveform      = v[4];
+        self.osc2_waveform      = v[5];
+        self.osc3_waveform      = v[6];
+        self.osc1_pulse_width   = 1.0 - v[7];
+        self.osc2_pulse_width   = 1.0 - v[8];
+        self.osc3_pulse_width   = 1.0 - v[9];
+        self.osc1_detune_coarse = v[10];
+        self.osc2_detune_coarse = v[11];
+        self.osc3_detune_coarse = v[12];
+        self.osc1_detune_fine   = v[13];
+        self.osc2_detune_fine   = v[14];
+        self.osc3_detune_fine   = v[15];
+        self.filter_type        = v[16].into();
+        self.filter_freq        = helpers::param_to_frequency(v[17]);
+        self.filter_resonance   = 1.0 - v[18];
+        self.filter_mod_amt     = v[19];
+        self.amp_attack         = helpers::scalar_to_env_value(v[20]);
+        self.amp_decay          = helpers::scalar_to_env_value(v[21]);
+        self.amp_sustain        = v[22];
+        self.amp_release        = helpers::scalar_to_env_value(v[23]);
+        self.mod_attack         = helpers::scalar_to_env_value(v[24]);
+        self.mod_decay          = helpers::scalar_to_env_value(v[25]);
+        self.mod_sustain        = v[26];
+        self.mod_release        = helpers::scalar_to_env_value(v[27]);
+        self.pitch_attack       = helpers::scalar_to_env_value(v[28]);
+        self.pitch_decay        = helpers::scalar_to_env_value(v[29]);
+        self.pitch_sustain      = v[30];
+        self.pitch_release      = helpers::scalar_to_env_value(v[31]);
+        self.pitch_env_amt      = (v[32] - 0.5) * 2.0 * 36.0;
+        self.vel_amp            = v[33];
+        self.vel_filter         = v[34];
+        self.vel_env            = v[35];
+        self.pressure_filter    = v[36];
+
+        self.dev_params.update(&self.params);
+        self.dev_params
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Oscillator {
     sample_rate: f64,
@@ -243,17 +306,19 @@ impl Voice<SlaughterParams> for SlaughterVoice {
         }
     }
     fn note_on(&mut self, data: &mut VoiceData, params: &mut SlaughterParams, note: i32, velocity: i32, detune: f32, pan: f32) {
-        println!("SLAUGHTER NOTE ON: {} => {}", note, helpers::note_to_freq(note.into()));
-        data.note_on(note, 0, detune, pan);
+        data.note_on(note, velocity, detune, pan);
 
-        self.amp_env.attack     = params.amp_attack;
-        self.amp_env.decay      = params.amp_decay;
+        // Harder hit notes get shorter attack and decay times:
+        let env_scale = 1.0 - 0.9 * params.vel_env * data.velocity;
+
+        self.amp_env.attack     = params.amp_attack * env_scale;
+        self.amp_env.decay      = params.amp_decay * env_scale;
         self.amp_env.sustain    = params.amp_sustain;
         self.amp_env.release    = params.amp_release;
         self.amp_env.trigger();
 
-        self.mod_env.attack     = params.mod_attack;
-        self.mod_env.decay      = params.mod_decay;
+        self.mod_env.attack     = params.mod_attack * env_scale;
+        self.mod_env.decay      = params.mod_decay * env_scale;
         self.mod_env.sustain    = params.mod_sustain;
         self.mod_env.release    = params.mod_release;
         self.mod_env.trigger();
@@ -266,7 +331,6 @@ impl Voice<SlaughterParams> for SlaughterVoice {
     }
     fn note_off(&mut self, data: &mut VoiceData, params: &mut SlaughterParams) {
         data.note_off();
-        data.is_on = false; // TODO: REMOVE ME IF WE HAVE ENVELOPES!
         self.amp_env.off();
         self.mod_env.off();
         self.pitch_env.off();
@@ -277,6 +341,12 @@ impl Voice<SlaughterParams> for SlaughterVoice {
     fn get_note(&mut self, data: &mut VoiceData, params: &mut SlaughterParams) -> f64 {
         data.get_note() as f64
     }
+    fn is_finished(&self, _data: &VoiceData, _params: &SlaughterParams) -> bool {
+        self.amp_env.state == EnvelopeState::Finished
+    }
+    fn level(&self, _data: &VoiceData, _params: &SlaughterParams) -> f32 {
+        self.amp_env.get_value()
+    }
     fn run(&mut self,
            data: &mut VoiceData,
            params: &mut SlaughterParams,
@@ -285,14 +355,11 @@ impl Voice<SlaughterParams> for SlaughterVoice {
            out_offs: usize,
            outputs: &mut [f32]) {
 
-        let base_note : f64 = data.get_note();
-
-        //let vibrato_freq = data.vibrato_freq / self.sample_rate;
-
         self.filter.set_type(params.filter_type);
         self.filter.set_q(params.filter_resonance);
 
-        let amp       = -16.0 * helpers::volume_to_scalar(params.master_level);
+        let amp       = -16.0 * helpers::volume_to_scalar(data.master_level)
+                        * (1.0 - params.vel_amp * (1.0 - data.velocity));
         let pan_left  = helpers::pan_to_scalar_left(data.pan);
         let pan_right = helpers::pan_to_scalar_right(data.pan);
 
@@ -308,30 +375,67 @@ impl Voice<SlaughterParams> for SlaughterVoice {
         let osc3_volume_scalar = params.osc3_volume * params.osc3_volume;
         let noise_scalar       = params.noise_volume * params.noise_volume;
 
-//        let mut fi = false;
-//        let mut f : f32 = 0.0;
-//        let mut l : f32 = 0.0;
+        let pitch_env_amt = params.pitch_env_amt as f64;
+        let filter_mod    = (20000.0 - 20.0) * (params.filter_mod_amt * 2.0 - 1.0);
+        let filter_vel    =
+            (20000.0 - 20.0) * (params.vel_filter * 2.0 - 1.0) * data.velocity;
+        let filter_prs    =
+            (20000.0 - 20.0) * (params.pressure_filter * 2.0 - 1.0) * data.pressure;
 
         for i in 0..sample_num {
+            let base_note : f64 =
+                data.get_note()
+                + (self.pitch_env.get_value() as f64) * pitch_env_amt;
+
             self.filter.set_freq(
                 helpers::clamp(
-                    params.filter_freq, 0.0, 20000.0 - 20.0));
-            let mut s =
-                self.osc1.next(
-                    base_note, params.osc1_waveform, params.osc1_pulse_width);
-
-//            if !fi { f = s; fi = true; }
-//            l = s;
-
-            let osc_mix = s;
-            s = self.filter.next(osc_mix);
-            outputs[out_offs + (i * 2)]     = s as f32;
-            outputs[out_offs + (i * 2) + 1] = s as f32;
+                    params.filter_freq
+                    + filter_vel
+                    + filter_prs
+                    + self.mod_env.get_value() * filter_mod,
+                    0.0, 20000.0 - 20.0));
+
+            let mut osc_mix : f32 = 0.0;
+            if osc1_volume_scalar > 0.0 {
+                osc_mix +=
+                    self.osc1.next(
+                        base_note + osc1_detune,
+                        params.osc1_waveform,
+                        params.osc1_pulse_width)
+                    * osc1_volume_scalar;
+            }
+            if osc2_volume_scalar > 0.0 {
+                osc_mix +=
+                    self.osc2.next(
+                        base_note + osc2_detune,
+                        params.osc2_waveform,
+                        params.osc2_pulse_width)
+                    * osc2_volume_scalar;
+            }
+            if osc3_volume_scalar > 0.0 {
+                osc_mix +=
+                    self.osc3.next(
+                        base_note + osc3_detune,
+                        params.osc3_waveform,
+                        params.osc3_pulse_width)
+                    * osc3_volume_scalar;
+            }
+            if noise_scalar > 0.0 {
+                osc_mix +=
+                    ((self.rg.next_open01() * 2.0 - 1.0) as f32)
+                    * noise_scalar;
+            }
+
+            let s = self.filter.next(osc_mix) * self.amp_env.get_value() * amp;
+            outputs[(out_offs + i) * 2]     += s * pan_left;
+            outputs[(out_offs + i) * 2 + 1] += s * pan_right;
 
             //d// println!("S {}", s);
-        }
 
-//        println!("OUT[{}]: {} => {}", base_note, f, l);
+            self.amp_env.next();
+            self.mod_env.next();
+            self.pitch_env.next();
+        }
     }
 }
 
@@ -350,10 +454,7 @@ impl Op for SynthDevice<SlaughterVoice, SlaughterParams> {
 
     fn event(&mut self, ev: &Event) {
         //d// println!("SLAU EVENT: {:?}", ev);
-        match ev {
-            Event::NoteOn(n)  => { self.note_on(*n as i32, 0, 0); },
-            Event::NoteOff(n) => { self.note_off(*n as i32, 0); },
-        }
+        self.op_event(ev);
     }
 
     fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
@@ -364,67 +465,25 @@ impl Op for SynthDevice<SlaughterVoice, SlaughterParams> {
         self.params.params.set(name, to, as_default)
     }
 
-    fn exec(&mut self, t: f32, regs: &mut [f32]) {
-        self.params.osc1_volume        = self.params.params.inputs[0].calc(regs);
-        self.params.osc2_volume        = self.params.params.inputs[1].calc(regs);
-        self.params.osc3_volume        = self.params.params.inputs[2].calc(regs);
-        self.params.noise_volume       = self.params.params.inputs[3].calc(regs);
-        self.params.osc1_waveform      = self.params.params.inputs[4].calc(regs);
-        self.params.osc2_waveform      = self.params.params.inputs[5].calc(regs);
-        self.params.osc3_waveform      = self.params.params.inputs[6].calc(regs);
-        self.params.osc1_pulse_width   = 1.0 - self.params.params.inputs[7].calc(regs);
-        self.params.osc2_pulse_width   = 1.0 - self.params.params.inputs[8].calc(regs);
-        self.params.osc3_pulse_width   = 1.0 - self.params.params.inputs[9].calc(regs);
-        self.params.osc1_detune_coarse = self.params.params.inputs[10].calc(regs);
-        self.params.osc2_detune_coarse = self.params.params.inputs[11].calc(regs);
-        self.params.osc3_detune_coarse = self.params.params.inputs[12].calc(regs);
-        self.params.osc1_detune_fine   = self.params.params.inputs[13].calc(regs);
-        self.params.osc2_detune_fine   = self.params.params.inputs[14].calc(regs);
-        self.params.osc3_detune_fine   = self.params.params.inputs[15].calc(regs);
-        self.params.filter_type        = self.params.params.inputs[16].calc(regs).into();
-        self.params.filter_freq        =
-            helpers::param_to_frequency(self.params.params.inputs[17].calc(regs));
-        self.params.filter_resonance   = 1.0 - self.params.params.inputs[18].calc(regs);
-        self.params.filter_mod_amt     = self.params.params.inputs[19].calc(regs);
-        self.params.amp_attack         =
-            helpers::scalar_to_env_value(self.params.params.inputs[20].calc(regs));
-        self.params.amp_decay          =
-            helpers::scalar_to_env_value(self.params.params.inputs[21].calc(regs));
-        self.params.amp_sustain        = self.params.params.inputs[22].calc(regs);
-        self.params.amp_release        =
-            helpers::scalar_to_env_value(self.params.params.inputs[23].calc(regs));
-        self.params.mod_attack         =
-            helpers::scalar_to_env_value(self.params.params.inputs[24].calc(regs));
-        self.params.mod_decay          =
-            helpers::scalar_to_env_value(self.params.params.inputs[25].calc(regs));
-        self.params.mod_sustain        = self.params.params.inputs[26].calc(regs);
-        self.params.mod_release        =
-            helpers::scalar_to_env_value(self.params.params.inputs[27].calc(regs));
-        self.params.pitch_attack       =
-            helpers::scalar_to_env_value(self.params.params.inputs[28].calc(regs));
-        self.params.pitch_decay        =
-            helpers::scalar_to_env_value(self.params.params.inputs[29].calc(regs));
-        self.params.pitch_sustain      = self.params.params.inputs[30].calc(regs);
-        self.params.pitch_release      =
-            helpers::scalar_to_env_value(self.params.params.inputs[31].calc(regs));
-        self.params.pitch_env_amt      =
-            (self.params.params.inputs[32].calc(regs) - 0.5) * 2.0 * 36.0;
-
-        // TODO: init and copy SynthDeviceParams to SynthDevice here!
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.exec_params(regs);
     }
 
     fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
     {
         let mut f : [f32; 1] = [0.0; 1];
         //d// println!("RENDER #samples={}", num_samples);
-        self.run(0.0, num_samples, &mut f, &mut bufs[input_idx][..]);
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.run(0.0, num_samples, &mut f, buf);
     }
 }
 
 pub fn new_slaughter(sample_rate: f64) -> SynthDevice<SlaughterVoice, SlaughterParams> {
     //d// println!("NEW SLAUGHTER!");
-    let params = SlaughterParams::new();
-    let sd : SynthDevice<SlaughterVoice, SlaughterParams> =
+    let params     = SlaughterParams::new();
+    let dev_params = params.dev_params;
+    let mut sd : SynthDevice<SlaughterVoice, SlaughterParams> =
         SynthDevice::new(sample_rate, params);
+    sd.set_dev_params(&dev_params);
     sd
 }
@@ -0,0 +1,203 @@
+use crate::delay_buffer::DelayBuffer;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Longest lookahead in milliseconds.
+const MAX_LOOKAHEAD   : f64   = 10.0;
+// The threshold goes from -THRESHOLD_RANGE to 0 dB.
+const THRESHOLD_RANGE : f32   = 48.0;
+const MAX_RATIO       : f32   = 20.0;
+// Attack and release ranges in milliseconds.
+const MIN_ATTACK      : f32   = 0.1;
+const MAX_ATTACK      : f32   = 100.0;
+const MIN_RELEASE     : f32   = 1.0;
+const MAX_RELEASE     : f32   = 1000.0;
+// Level below which the detector is treated as silence.
+const MIN_LEVEL       : f32   = 0.000001;
+const NUM_PORTS       : usize = 8;
+
+// Quadratic mapping for finer control over the short times.
+fn param_to_ms(param: f32, min: f32, max: f32) -> f32 {
+    let p = helpers::clamp(param, 0.0, 1.0);
+    min + (max - min) * p * p
+}
+
+/// A feed forward compressor/limiter. The detector either listens
+/// to the processed bus itself or to a sidechain bus selected
+/// with the "sc_bus" port.
+pub struct Smasher {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    sidechain_bus:  Option<usize>,
+    input_gain:     f32,
+    threshold:      f32,
+    ratio:          f32,
+    attack:         f32,
+    release:        f32,
+    lookahead:      f32,
+    output_gain:    f32,
+    envelope:       f32,
+    gain_reduction: f32,
+    delay_l:        DelayBuffer,
+    delay_r:        DelayBuffer,
+}
+
+impl Smasher {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("sc_bus",     -1.0, 63.0, -1.0);
+        p.input("in_gain",    0.0,  1.0,  0.5);
+        p.input("thresh",     0.0,  1.0,  0.75);
+        p.input("ratio",      0.0,  1.0,  0.158);
+        p.input("att",        0.0,  1.0,  0.095);
+        p.input("rel",        0.0,  1.0,  0.315);
+        p.input("look",       0.0,  1.0,  0.0);
+        p.input("out_gain",   0.0,  1.0,  0.5);
+
+        let delay_len = (MAX_LOOKAHEAD * 0.001 * sample_rate) as usize + 1;
+
+        let mut s = Smasher {
+            sample_rate,
+            sidechain_bus:  None,
+            input_gain:     0.0,
+            threshold:      0.0,
+            ratio:          0.0,
+            attack:         0.0,
+            release:        0.0,
+            lookahead:      0.0,
+            output_gain:    0.0,
+            envelope:       0.0,
+            gain_reduction: 0.0,
+            delay_l:        DelayBuffer::new(delay_len),
+            delay_r:        DelayBuffer::new(delay_len),
+
+            params:         p,
+        };
+        let v : Vec<f32> = (0..NUM_PORTS).map(|i| s.params.v(i)).collect();
+        s.set(&v);
+        s
+    }
+
+    fn set(&mut self, v: &[f32]) {
+        let sc_bus = v[0].round();
+        self.sidechain_bus =
+            if sc_bus >= 0.0 { Some(sc_bus as usize) } else { None };
+        self.input_gain  = helpers::db_to_scalar(helpers::param_to_db(v[1], 12.0));
+        self.threshold   =
+            helpers::param_to_db(v[2], THRESHOLD_RANGE * 0.5)
+            - THRESHOLD_RANGE * 0.5;
+        self.ratio       = 1.0 + (MAX_RATIO - 1.0) * helpers::clamp(v[3], 0.0, 1.0);
+        self.attack      = param_to_ms(v[4], MIN_ATTACK, MAX_ATTACK);
+        self.release     = param_to_ms(v[5], MIN_RELEASE, MAX_RELEASE);
+        self.lookahead   = helpers::clamp(v[6], 0.0, 1.0) * MAX_LOOKAHEAD as f32;
+        self.output_gain = helpers::db_to_scalar(helpers::param_to_db(v[7], 12.0));
+    }
+
+    /// The current gain reduction in dB, 0.0 or below.
+    pub fn gain_reduction(&self) -> f32 { self.gain_reduction }
+
+    fn time_coef(&self, ms: f32) -> f32 {
+        (-1.0 / (ms.max(0.01) as f64 * 0.001 * self.sample_rate)).exp() as f32
+    }
+
+    /// The detector uses `sidechain` instead of `buf` if it is given.
+    pub fn process(&mut self, buf: &mut [f32], sidechain: Option<&[f32]>) {
+        let att_coef  = self.time_coef(self.attack);
+        let rel_coef  = self.time_coef(self.release);
+        let lookahead =
+            (self.lookahead as f64 * 0.001 * self.sample_rate) as usize;
+        let slope     = 1.0 - 1.0 / self.ratio;
+
+        for (i, frame) in buf.chunks_mut(2).enumerate() {
+            if frame.len() < 2 { break; }
+
+            let in_l = frame[0] * self.input_gain;
+            let in_r = frame[1] * self.input_gain;
+
+            let level =
+                match sidechain {
+                    Some(sc) if sc.len() >= (i + 1) * 2 =>
+                        sc[i * 2].abs().max(sc[i * 2 + 1].abs()),
+                    _ => in_l.abs().max(in_r.abs()),
+                };
+
+            let coef = if level > self.envelope { att_coef } else { rel_coef };
+            self.envelope = level + coef * (self.envelope - level);
+
+            let over =
+                helpers::scalar_to_db(self.envelope.max(MIN_LEVEL))
+                - self.threshold;
+            self.gain_reduction = if over > 0.0 { -over * slope } else { 0.0 };
+
+            let gain =
+                helpers::db_to_scalar(self.gain_reduction) * self.output_gain;
+
+            self.delay_l.feed(in_l);
+            self.delay_r.feed(in_r);
+            frame[0] = self.delay_l.tap(lookahead) * gain;
+            frame[1] = self.delay_r.tap(lookahead) * gain;
+        }
+    }
+}
+
+impl Op for Smasher {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        let mut v = [0.0; NUM_PORTS];
+        for (i, v) in v.iter_mut().enumerate() {
+            *v = self.params.inputs[i].calc(regs);
+        }
+        self.set(&v);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let range = (offs * 2)..((offs + num_samples) * 2);
+
+        match self.sidechain_bus {
+            Some(sc) if sc != input_idx
+                        && sc < bufs.len()
+                        && bufs[sc].len() >= range.end => {
+                let (buf, sc_buf) =
+                    if sc < input_idx {
+                        let (l, r) = bufs.split_at_mut(input_idx);
+                        (&mut r[0], &l[sc])
+                    } else {
+                        let (l, r) = bufs.split_at_mut(sc);
+                        (&mut l[input_idx], &r[0])
+                    };
+                self.process(&mut buf[range.clone()], Some(&sc_buf[range]));
+            },
+            _ => {
+                self.process(&mut bufs[input_idx][range], None);
+            },
+        }
+    }
+}
+
+pub fn new_smasher(sample_rate: f64) -> Smasher {
+    Smasher::new(sample_rate)
+}
@@ -1,14 +1,45 @@
 use crate::parameters::*;
 use crate::helpers::SignalIOParams;
 use crate::helpers;
+use wctr_signal_ops::signals;
 
-const MAX_DEV_PARAMS : usize = 9;
+const MAX_DEV_PARAMS : usize = 13;
+const MAX_EVENTS     : usize = 256;
+const MAX_VOICES     : usize = 256;
+
+// Length of the fade out of stolen voices in seconds.
+const STEAL_FADE_TIME : f64 = 0.005;
+// Stolen voices are rendered in chunks of at most this many frames.
+const FADE_BUF_LEN    : usize = 4096;
+
+const CC_MOD_WHEEL : usize = 1;
+const CC_SUSTAIN   : usize = 64;
+const CC_SOSTENUTO : usize = 66;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
-enum EventType {
+pub enum EventType {
     None,
     NoteOn,
     NoteOff,
+    AllNotesOff,
+    PitchBend,
+    ChannelPressure,
+    PolyPressure,
+    ControlChange,
+}
+
+impl EventType {
+    // Determines the processing order of events at the same sample.
+    // Releasing notes comes first, so that a note on at the same
+    // sample can reuse the voices and is never cut off.
+    fn order(&self) -> i32 {
+        match self {
+            EventType::NoteOff     => 0,
+            EventType::AllNotesOff => 0,
+            EventType::NoteOn      => 2,
+            _                      => 1,
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -17,6 +48,7 @@ struct Event {
     delta_samples: i32,
     note:          i32,
     velocity:      i32,
+    value:         f32,
 }
 
 impl Event {
@@ -26,11 +58,83 @@ impl Event {
             delta_samples: 0,
             note:          0,
             velocity:      0,
+            value:         0.0,
+        }
+    }
+
+    fn sorts_before(&self, other: &Event) -> bool {
+        if self.delta_samples == other.delta_samples {
+            self.typ.order() < other.typ.order()
+        } else {
+            self.delta_samples < other.delta_samples
+        }
+    }
+}
+
+// Fixed size event queue, that is kept sorted by ascending
+// delta_samples. Events with the same delta_samples are ordered
+// by EventType::order() and keep their insertion order otherwise.
+#[derive(Debug, Clone)]
+struct EventQueue {
+    events:  [Event; MAX_EVENTS],
+    len:     usize,
+    dropped: usize,
+}
+
+impl EventQueue {
+    fn new() -> Self {
+        EventQueue {
+            events:  [Event::new(); MAX_EVENTS],
+            len:     0,
+            dropped: 0,
+        }
+    }
+
+    fn push(&mut self, mut ev: Event) -> bool {
+        if self.len >= MAX_EVENTS {
+            self.dropped += 1;
+            return false;
+        }
+
+        if ev.delta_samples < 0 {
+            ev.delta_samples = 0;
+        }
+
+        let mut idx = self.len;
+        while idx > 0 && ev.sorts_before(&self.events[idx - 1]) {
+            idx -= 1;
+        }
+
+        self.events.copy_within(idx..self.len, idx + 1);
+        self.events[idx] = ev;
+        self.len += 1;
+        true
+    }
+
+    fn next_delta(&self) -> Option<i32> {
+        if self.len > 0 { Some(self.events[0].delta_samples) }
+        else            { None }
+    }
+
+    fn pop_due(&mut self) -> Option<Event> {
+        if self.len == 0 || self.events[0].delta_samples > 0 {
+            return None;
+        }
+
+        let ev = self.events[0];
+        self.events.copy_within(1..self.len, 0);
+        self.len -= 1;
+        Some(ev)
+    }
+
+    fn advance(&mut self, samples: i32) {
+        for ev in self.events[0..self.len].iter_mut() {
+            ev.delta_samples -= samples;
         }
     }
 
     fn clear(&mut self) {
-        self.typ = EventType::None;
+        self.len = 0;
     }
 }
 
@@ -38,10 +142,26 @@ impl Event {
 pub struct VoiceData {
     pub sample_rate:      f64,
     pub is_on:            bool,
+    pub gate:             bool,
     pub note:             i32,
+    pub velocity:         f32,
     pub detune:           f32,
     pub pan:              f32,
+    pub master_level:     f32,
+    pub pitch_bend:       f64,
+    pub mod_wheel:        f32,
+    /// Channel plus polyphonic aftertouch of the note, 0.0 to 1.0.
+    pub pressure:         f32,
     pub vibrato_phase:    f64,
+    pub vibrato_freq:     f64,
+    pub vibrato_amount:   f32,
+    pub rise:             f32,
+    /// Counts the note on events of the device, all unisono voices
+    /// started by the same note on share the same id.
+    pub note_id:          u64,
+        rise_pos:         f64,
+        started:          u64,
+        fade_left:        i32,
         slide_active:     bool,
         slide_delta:      f64,
         slide_samples:    i32,
@@ -50,14 +170,27 @@ pub struct VoiceData {
 }
 
 impl VoiceData {
-    fn new(sample_rate: f64) -> VoiceData {
+    pub(crate) fn new(sample_rate: f64) -> VoiceData {
         VoiceData {
             sample_rate,
             is_on:            false,
+            gate:             false,
             note:             0,
+            velocity:         1.0,
             detune:           0.0,
             pan:              0.5,
+            master_level:     1.0,
+            pitch_bend:       0.0,
+            mod_wheel:        0.0,
+            pressure:         0.0,
             vibrato_phase:    0.0,
+            vibrato_freq:     0.0,
+            vibrato_amount:   0.0,
+            rise:             0.0,
+            note_id:          0,
+            rise_pos:         0.0,
+            started:          0,
+            fade_left:        0,
             slide_active:     false,
             slide_delta:      0.0,
             slide_samples:    0,
@@ -66,16 +199,21 @@ impl VoiceData {
         }
     }
 
-    pub fn note_on(&mut self, note: i32, _velocity: i32, detune: f32, pan: f32) {
+    pub fn note_on(&mut self, note: i32, velocity: i32, detune: f32, pan: f32) {
         self.is_on        = true;
+        self.gate         = true;
         self.note         = note;
+        self.velocity     = helpers::velocity_to_scalar(velocity);
         self.detune       = detune;
         self.pan          = pan;
         self.current_note = note as f64;
         self.slide_active = false;
+        self.rise_pos     = 0.0;
+        self.fade_left    = 0;
     }
 
     pub fn note_off(&mut self) {
+        self.gate = false;
     }
 
     pub fn note_slide(&mut self, slide: f32, note: i32) {
@@ -101,15 +239,53 @@ impl VoiceData {
         }
 
         self.current_note
+        + (self.detune as f64)
+        + self.pitch_bend
+        + self.next_vibrato()
+    }
+
+    // Advances the vibrato LFO by one sample and returns the current
+    // pitch offset in semitones. The amount is faded in over the
+    // rise time after the note on. The modulation wheel adds to
+    // the vibrato amount.
+    fn next_vibrato(&mut self) -> f64 {
+        let mut amount = (self.vibrato_amount + self.mod_wheel) as f64;
+
+        if self.rise > 0.0 {
+            let rise_time = helpers::param_to_rise_time(self.rise);
+            if self.rise_pos < rise_time {
+                amount        *= self.rise_pos / rise_time;
+                self.rise_pos += 1.0 / self.sample_rate;
+            }
+        }
+
+        let offs =
+            if amount > 0.0 {
+                helpers::fast_sin(self.vibrato_phase * 2.0 * std::f64::consts::PI)
+                * amount
+            } else {
+                0.0
+            };
+
+        self.vibrato_phase =
+            (self.vibrato_phase + self.vibrato_freq / self.sample_rate).fract();
+
+        offs
     }
 }
 
-pub trait Voice<P>: Copy + Clone {
+pub trait Voice<P>: Clone {
     fn new(sample_rate: f64) -> Self;
     fn note_on(&mut self, data: &mut VoiceData, params: &mut P, note: i32, velocity: i32, detune: f32, pan: f32);
     fn note_off(&mut self, data: &mut VoiceData, params: &mut P);
     fn note_slide(&mut self, data: &mut VoiceData, params: &mut P, slide: f32, note: i32);
     fn get_note(&mut self, data: &mut VoiceData, params: &mut P) -> f64;
+    /// Returns true once the voice has faded out after its note off
+    /// and can be freed by the `SynthDevice`.
+    fn is_finished(&self, data: &VoiceData, params: &P) -> bool;
+    /// Returns the current amplitude of the voice, which is used
+    /// to find the quietest voice when stealing voices.
+    fn level(&self, data: &VoiceData, params: &P) -> f32;
     fn run(&mut self,
            data: &mut VoiceData,
            params: &mut P,
@@ -119,7 +295,9 @@ pub trait Voice<P>: Copy + Clone {
            outputs: &mut [f32]);
 }
 
+#[derive(Debug, PartialEq, Copy, Clone)]
 pub struct SynthDeviceParams {
+    param_offs:     usize,
     master_level:   f32,
     voices_unisono: i32,
     voices_detune:  f32,
@@ -129,12 +307,17 @@ pub struct SynthDeviceParams {
     rise:           f32,
     slide:          f32,
     voice_mode:     VoiceMode,
+    max_voices:     usize,
+    steal_mode:     StealMode,
+    bend_range:     f32,
+    velocity:       i32,
 }
 
 impl SynthDeviceParams {
     pub fn new() -> SynthDeviceParams {
         SynthDeviceParams {
-            master_level:   0.0,
+            param_offs:     0,
+            master_level:   1.0,
             voices_unisono: 1,
             voices_detune:  0.0,
             voices_pan:     0.5,
@@ -143,19 +326,27 @@ impl SynthDeviceParams {
             rise:           0.0,
             slide:          0.0,
             voice_mode:     VoiceMode::Polyphonic,
+            max_voices:     MAX_VOICES,
+            steal_mode:     StealMode::Oldest,
+            bend_range:     2.0,
+            velocity:       127,
         }
     }
     pub fn new_with_params(p: &mut SignalIOParams) -> SynthDeviceParams {
         let mut dev_params = SynthDeviceParams::new();
-        dev_params.init_params(&mut p);
+        dev_params.init_params(p);
         dev_params
     }
 
+    // The device parameters are appended to the ports of the
+    // voice parameters, so the voice parameter indices stay stable.
     fn init_params(&mut self, p: &mut SignalIOParams) {
+        self.param_offs = p.inputs.len();
+
         p.input("m_vol",      0.0, 1.0, 1.0);
         p.input("v_uniso",    0.0, 1.0, 0.0);
         p.input("v_detune",   0.0, 1.0, 0.0);
-        p.input("v_pan",      0.0, 1.0, 0.0);
+        p.input("v_pan",      0.0, 1.0, 0.5);
 
         p.input("vi_f",       0.0, 1.0, 0.0);
         p.input("vi_amt",     0.0, 1.0, 0.0);
@@ -164,35 +355,58 @@ impl SynthDeviceParams {
         p.input("slide_t",    0.0, 1.0, 0.0);
         p.input("v_mode",     0.0, 1.0, 0.0);
 
-        self.master_level       = p.v(0);
-        self.voices_unisono     = helpers::param_to_unisono(p.v(1));
-        self.voices_detune      = p.v(2);
-        self.voices_pan         = p.v(3);
-        self.vibrato_freq       = helpers::param_to_vibrato_freq(p.v(4));
-        self.vibrato_amount     = p.v(5);
-        self.rise               = p.v(6);
-        self.slide              = p.v(7);
-        self.set_voice_mode(p.v(8).into());
-    }
-
-    fn exec(&mut self, t: f32, regs: &mut [f32]) {
-        self.params.master_level       = self.params.params.inputs[4].calc(regs);
-        self.params.vibrato_freq       =
-            helpers::param_to_vibrato_freq(self.params.params.inputs[34].calc(regs));
-        self.params.vibrato_amount     = self.params.params.inputs[35].calc(regs);
-        self.params.rise               = self.params.params.inputs[36].calc(regs);
-
-        // TODO calc the other params?!
-
-//        let a = self.values[0].calc(regs);
-//        let p = self.values[1].calc(regs);
-//        let v = self.values[2].calc(regs);
-//        let f = self.values[3].calc(regs);
-//        regs[self.out] = a * (((f * t) + p).sin() + v);
-        //d// println!("OUT: {}, {}", regs[self.out], self.out);
+        p.input("v_poly",     0.0, 1.0, 1.0);
+        p.input("v_steal",    0.0, 1.0, 0.0);
+
+        p.input("pb_range",   0.0, 1.0, helpers::bend_range_to_param(2.0));
+        p.input("vel",        0.0, 1.0, 1.0);
+
+        let o = self.param_offs;
+        self.master_level       = p.v(o);
+        self.voices_unisono     = helpers::param_to_unisono(p.v(o + 1));
+        self.voices_detune      = p.v(o + 2);
+        self.voices_pan         = p.v(o + 3);
+        self.vibrato_freq       = helpers::param_to_vibrato_freq(p.v(o + 4));
+        self.vibrato_amount     = p.v(o + 5);
+        self.rise               = p.v(o + 6);
+        self.slide              = p.v(o + 7);
+        self.voice_mode         = p.v(o + 8).into();
+        self.max_voices         = helpers::param_to_polyphony(p.v(o + 9));
+        self.steal_mode         = p.v(o + 10).into();
+        self.bend_range         = helpers::param_to_bend_range(p.v(o + 11));
+        self.velocity           = helpers::param_to_velocity(p.v(o + 12));
+    }
+
+    /// Updates the parameters from the port values, which were
+    /// calculated by `SignalIOParams::calc_all`.
+    pub fn update(&mut self, p: &SignalIOParams) {
+        let v : &[f32] =
+            &p.values[self.param_offs..(self.param_offs + MAX_DEV_PARAMS)];
+
+        self.master_level       = v[0];
+        self.voices_unisono     = helpers::param_to_unisono(v[1]);
+        self.voices_detune      = v[2];
+        self.voices_pan         = v[3];
+        self.vibrato_freq       = helpers::param_to_vibrato_freq(v[4]);
+        self.vibrato_amount     = v[5];
+        self.rise               = v[6];
+        self.slide              = v[7];
+        self.voice_mode         = v[8].into();
+        self.max_voices         = helpers::param_to_polyphony(v[9]);
+        self.steal_mode         = v[10].into();
+        self.bend_range         = helpers::param_to_bend_range(v[11]);
+        self.velocity           = helpers::param_to_velocity(v[12]);
     }
 }
 
+/// The parameters shared by the voices of a `SynthDevice`.
+pub trait VoiceParams: AsMut<SignalIOParams> {
+    /// Converts the port values in `SignalIOParams::values` into
+    /// the voice parameters and returns the device parameters.
+    /// Called on each exec and when a mapped MIDI CC arrives.
+    fn update(&mut self) -> SynthDeviceParams;
+}
+
 // HOW DO I GET THIS SORTED OUT?
 // SynthDevice needs access to these params, and the voices too.
 // The only way to provide both access is an Rc/RefCell.
@@ -207,9 +421,21 @@ pub struct SynthDevice<V, P>
     note_log:       [i32; 128],
     note_count:     i32,
     active_notes:   [bool; 128],
-    voice_data:     [VoiceData; 256],
-    voices:         [V; 256],
-    events:         [Event; 256],
+    voice_data:     [VoiceData; MAX_VOICES],
+    voices:         Vec<V>,
+    fade_buf:       Vec<f32>,
+    note_on_count:  u64,
+    note_id_count:  u64,
+    pitch_bend:     f32,
+    chan_pressure:  f32,
+    poly_pressure:  [f32; 128],
+    cc_values:      [f32; 128],
+    keys_down:      [bool; 128],
+    deferred_off:   [bool; 128],
+    sustain:        bool,
+    sostenuto:      bool,
+    sostenuto_keys: [bool; 128],
+    events:         EventQueue,
     dev_params:     SynthDeviceParams,
 pub params:         P,
 }
@@ -226,27 +452,34 @@ macro_rules! voice_data_zip {
 
 macro_rules! detuned_notes_on {
     ($self: ident, $e: ident, $j: ident) => {
-        for (v, vd) in voice_data_zip!($self) {
-            if $j <= 0 { break; }
-
-            if !vd.is_on {
-                $j -= 1;
-                let f = if $self.voices_unisono > 1 {
-                    $j as f32 / ($self.voices_unisono as f32 - 1.0)
-                } else {
-                    $j as f32
+        while $j > 0 {
+            let idx =
+                match $self.alloc_voice($e.note) {
+                    Some(idx) => idx,
+                    None      => break,
                 };
 
-                v.note_on(
-                    vd, &mut $self.params, $e.note, $e.velocity,
-                    f * $self.voices_detune,
-                    (f - 0.5) * ($self.voices_pan * 2.0 - 1.0) + 0.5);
-            }
+            $j -= 1;
+            $self.voice_data[idx].note_id = $self.note_id_count;
+            let f = if $self.dev_params.voices_unisono > 1 {
+                $j as f32 / ($self.dev_params.voices_unisono as f32 - 1.0)
+            } else {
+                $j as f32
+            };
+
+            $self.voices[idx].note_on(
+                &mut $self.voice_data[idx], &mut $self.params,
+                $e.note, $e.velocity,
+                f * $self.dev_params.voices_detune,
+                (f - 0.5) * ($self.dev_params.voices_pan * 2.0 - 1.0) + 0.5);
+
+            $self.note_on_count += 1;
+            $self.voice_data[idx].started = $self.note_on_count;
         }
     }
 }
 
-impl<P, V: Voice<P>> SynthDevice<V, P> {
+impl<P: VoiceParams, V: Voice<P>> SynthDevice<V, P> {
     pub fn new(sample_rate: f64, params: P) -> Self {
         SynthDevice {
             sample_rate,
@@ -254,9 +487,21 @@ impl<P, V: Voice<P>> SynthDevice<V, P> {
             note_count:     0,
             active_notes:   [false; 128],
             note_log:       [0; 128],
-            voice_data:     [VoiceData::new(sample_rate); 256],
-            voices:         [V::new(sample_rate); 256],
-            events:         [Event::new(); 256],
+            voice_data:     [VoiceData::new(sample_rate); MAX_VOICES],
+            voices:         (0..MAX_VOICES).map(|_| V::new(sample_rate)).collect(),
+            fade_buf:       vec![0.0; 2 * FADE_BUF_LEN],
+            note_on_count:  0,
+            note_id_count:  0,
+            pitch_bend:     0.0,
+            chan_pressure:  0.0,
+            poly_pressure:  [0.0; 128],
+            cc_values:      [0.0; 128],
+            keys_down:      [false; 128],
+            deferred_off:   [false; 128],
+            sustain:        false,
+            sostenuto:      false,
+            sostenuto_keys: [false; 128],
+            events:         EventQueue::new(),
             dev_params:     SynthDeviceParams::new(),
             params,
         }
@@ -267,123 +512,85 @@ impl<P, V: Voice<P>> SynthDevice<V, P> {
                _inputs: &mut [f32],
                outputs: &mut [f32]) {
 
-        let orig_num_samples = num_samples;
         clear_outputs(outputs);
         let mut out_offs = 0;
 
         while num_samples > 0 {
-            let mut samples_to_next_event = num_samples as i32;
-
-            for e in self.events.iter_mut() {
-                if e.typ == EventType::None {
-                    continue;
-                }
+            while let Some(e) = self.events.pop_due() {
+                self.handle_event(&e);
+            }
 
-                if e.delta_samples == 0 {
-                    match e.typ {
-                        EventType::NoteOn => {
-                            let mut j = self.voices_unisono;
-                            match self.voice_mode {
-                                VoiceMode::Polyphonic => {
-                                    detuned_notes_on!(self, e, j);
-                                },
-                                VoiceMode::MonoLegatoTrill => {
-                                    self.active_notes[e.note as usize] = true;
-                                    self.note_log[self.note_count as usize] = e.note;
-
-                                    if !self.mono_active { // no current note active, start new one
-                                        self.mono_active = true;
-                                        detuned_notes_on!(self, e, j);
-
-                                    } else { // mono note active, slide to new note
-                                        for (v, vd) in voice_data_zip!(self) {
-                                            if vd.is_on {
-                                                v.note_slide(vd, &mut self.params, self.slide, e.note);
-                                            }
-                                        }
-                                    }
-                                },
-                            }
-                        },
-                        EventType::NoteOff => {
-                            match self.voice_mode {
-                                VoiceMode::Polyphonic => {
-                                    for (v, vd) in voice_data_zip!(self) {
-                                        if vd.is_on && vd.note == e.note {
-                                            v.note_off(vd, &mut self.params);
-                                        }
-                                    }
-                                },
-                                VoiceMode::MonoLegatoTrill => {
-                                    self.active_notes[e.note as usize] = false;
-                                    let log_note =
-                                        self.note_log[(self.note_count - 1) as usize];
-                                    if e.note == log_note {
-                                        while self.note_count > 0 {
-
-                                            if self.active_notes[
-                                                self.note_log[
-                                                    (self.note_count - 1)
-                                                    as usize]
-                                                as usize] {
-
-                                                for (v, vd) in voice_data_zip!(self) {
-                                                    if vd.is_on {
-                                                        v.note_slide(
-                                                            vd,
-                                                            &mut self.params,
-                                                            self.slide,
-                                                            self.note_log[
-                                                                (self.note_count - 1)
-                                                                as usize]);
-                                                    }
-                                                }
-                                                break;
-                                            }
-
-                                            self.note_count -= 1;
-                                        }
-
-                                        if self.note_count == 0 {
-                                            self.mono_active = false;
-                                            for an in self.active_notes.iter_mut() {
-                                                *an = false;
-                                            }
-
-                                            for (v, vd) in voice_data_zip!(self) {
-                                                if vd.is_on {
-                                                    v.note_off(vd, &mut self.params);
-                                                }
-                                            }
-                                        }
-                                    }
-                                },
-                            }
-                        },
-                        _ => (),
-                    }
+            let samples_to_next_event =
+                match self.events.next_delta() {
+                    Some(delta) if (delta as usize) < num_samples => delta,
+                    _ => num_samples as i32,
+                };
 
-                    e.typ = EventType::None;
+            let block_len = samples_to_next_event as usize;
 
-                } else if e.delta_samples < samples_to_next_event {
-                    samples_to_next_event = e.delta_samples;
-                }
-            }
+            let fade_len = ((self.sample_rate * STEAL_FADE_TIME) as i32).max(1);
 
             let mut cnt = 0;
             for (v, vd) in self.voices.iter_mut().zip(self.voice_data.iter_mut()) {
                 if vd.is_on {
                     cnt += 1;
-                    v.run(vd, &mut self.params, song_pos, num_samples, out_offs, outputs);
+                    vd.master_level   = self.dev_params.master_level;
+                    vd.vibrato_freq   = self.dev_params.vibrato_freq;
+                    vd.vibrato_amount = self.dev_params.vibrato_amount;
+                    vd.rise           = self.dev_params.rise;
+                    vd.pitch_bend     =
+                        (self.pitch_bend * self.dev_params.bend_range) as f64;
+                    vd.mod_wheel      = self.cc_values[CC_MOD_WHEEL];
+                    vd.pressure       =
+                        helpers::clamp(
+                            self.chan_pressure
+                            + self.poly_pressure[vd.note.clamp(0, 127) as usize],
+                            0.0, 1.0);
+
+                    if vd.fade_left > 0 {
+                        // Stolen voices are rendered separately and
+                        // faded out to prevent clicks. Only the frames
+                        // up to the end of the fade are rendered:
+                        let mut done = 0;
+                        while done < block_len && vd.fade_left > 0 {
+                            let len =
+                                (block_len - done)
+                                .min(vd.fade_left as usize)
+                                .min(FADE_BUF_LEN);
+
+                            let fade_buf = &mut self.fade_buf[0..(len * 2)];
+                            clear_outputs(fade_buf);
+                            v.run(vd, &mut self.params,
+                                  song_pos + done as f64 / self.sample_rate,
+                                  len, 0, fade_buf);
+
+                            for i in 0..len {
+                                let gain = vd.fade_left as f32 / fade_len as f32;
+                                let o    = out_offs + done + i;
+                                outputs[o * 2]     += fade_buf[i * 2] * gain;
+                                outputs[o * 2 + 1] += fade_buf[i * 2 + 1] * gain;
+                                vd.fade_left -= 1;
+                            }
+                            done += len;
+                        }
+
+                        if vd.fade_left <= 0 {
+                            vd.is_on = false;
+                        }
+
+                    } else {
+                        v.run(vd, &mut self.params, song_pos,
+                              block_len, out_offs, outputs);
+                    }
+
+                    if !vd.gate && v.is_finished(vd, &self.params) {
+                        vd.is_on = false;
+                    }
                 }
             }
             //d// println!("VOICES ON: {}", cnt);
 
-            for e in self.events.iter_mut() {
-                if e.typ != EventType::None {
-                    e.delta_samples -= samples_to_next_event;
-                }
-            }
+            self.events.advance(samples_to_next_event);
 
             song_pos    += samples_to_next_event as f64 / self.sample_rate;
             out_offs    += samples_to_next_event as usize;
@@ -391,62 +598,414 @@ impl<P, V: Voice<P>> SynthDevice<V, P> {
         }
     }
 
-    fn all_notes_off(&mut self)
-    {
+    fn handle_event(&mut self, e: &Event) {
+        match e.typ {
+            EventType::NoteOn      => self.handle_note_on(e),
+            EventType::NoteOff     => self.handle_note_off(e),
+            EventType::AllNotesOff => self.release_all_notes(),
+            EventType::PitchBend   => { self.pitch_bend = e.value; },
+            EventType::ChannelPressure => {
+                self.chan_pressure = e.value;
+            },
+            EventType::PolyPressure => {
+                self.poly_pressure[e.note as usize] = e.value;
+            },
+            EventType::ControlChange => {
+                self.cc_values[e.note as usize] = e.value;
+                // Events are handled at the start of a block, so
+                // mapped ports change sample accurately:
+                if self.params.as_mut().control_change(e.note as u8, e.value) {
+                    let dev_params = self.params.update();
+                    self.set_dev_params(&dev_params);
+                }
+
+                match e.note as usize {
+                    CC_SUSTAIN   => self.set_sustain(e.value >= 0.5),
+                    CC_SOSTENUTO => self.set_sostenuto(e.value >= 0.5),
+                    _            => (),
+                }
+            },
+            EventType::None        => (),
+        }
+    }
+
+    fn handle_note_on(&mut self, e: &Event) {
+        self.keys_down[e.note as usize]    = true;
+        self.deferred_off[e.note as usize] = false;
+        self.note_id_count += 1;
+
+        let mut j = self.dev_params.voices_unisono;
+        match self.dev_params.voice_mode {
+            VoiceMode::Polyphonic => {
+                detuned_notes_on!(self, e, j);
+            },
+            VoiceMode::MonoLegatoTrill => {
+                self.active_notes[e.note as usize] = true;
+                if (self.note_count as usize) < self.note_log.len() {
+                    self.note_log[self.note_count as usize] = e.note;
+                    self.note_count += 1;
+                }
+
+                if !self.mono_active { // no current note active, start new one
+                    self.mono_active = true;
+                    detuned_notes_on!(self, e, j);
+
+                } else { // mono note active, slide to new note
+                    for (v, vd) in voice_data_zip!(self) {
+                        if vd.gate {
+                            v.note_slide(vd, &mut self.params, self.dev_params.slide, e.note);
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    fn handle_note_off(&mut self, e: &Event) {
+        let note = e.note as usize;
+        self.keys_down[note] = false;
+
+        if self.is_note_held(note) {
+            self.deferred_off[note] = true;
+            return;
+        }
+
+        self.release_note(e);
+    }
+
+    fn is_note_held(&self, note: usize) -> bool {
+        self.sustain || (self.sostenuto && self.sostenuto_keys[note])
+    }
+
+    fn set_sustain(&mut self, on: bool) {
+        self.sustain = on;
+        if !on { self.release_deferred_notes(); }
+    }
+
+    // Sostenuto only holds the keys that are down when the
+    // pedal is pressed.
+    fn set_sostenuto(&mut self, on: bool) {
+        if on == self.sostenuto { return; }
+
+        self.sostenuto = on;
+        if on {
+            self.sostenuto_keys = self.keys_down;
+        } else {
+            for sk in self.sostenuto_keys.iter_mut() {
+                *sk = false;
+            }
+            self.release_deferred_notes();
+        }
+    }
+
+    // Sends the note offs that were deferred by the pedals.
+    fn release_deferred_notes(&mut self) {
+        let mut ev = Event::new();
+        ev.typ = EventType::NoteOff;
+
+        match self.dev_params.voice_mode {
+            VoiceMode::Polyphonic => {
+                for note in 0..self.deferred_off.len() {
+                    if self.deferred_off[note] && !self.is_note_held(note) {
+                        self.deferred_off[note] = false;
+                        ev.note = note as i32;
+                        self.release_note(&ev);
+                    }
+                }
+            },
+            VoiceMode::MonoLegatoTrill => {
+                // Mark all released notes as inactive first, so that
+                // the note log is only walked back once and does not
+                // slide to notes that are released too.
+                for note in 0..self.deferred_off.len() {
+                    if self.deferred_off[note] && !self.is_note_held(note) {
+                        self.deferred_off[note] = false;
+                        self.active_notes[note] = false;
+                    }
+                }
+
+                if self.note_count > 0 {
+                    let top_note = self.note_log[(self.note_count - 1) as usize];
+                    if !self.active_notes[top_note as usize] {
+                        ev.note = top_note;
+                        self.release_note(&ev);
+                    }
+                }
+            },
+        }
+    }
+
+    fn release_note(&mut self, e: &Event) {
+        match self.dev_params.voice_mode {
+            VoiceMode::Polyphonic => {
+                for (v, vd) in voice_data_zip!(self) {
+                    if vd.gate && vd.note == e.note {
+                        v.note_off(vd, &mut self.params);
+                    }
+                }
+            },
+            VoiceMode::MonoLegatoTrill => {
+                self.active_notes[e.note as usize] = false;
+                if self.note_count > 0
+                   && e.note == self.note_log[(self.note_count - 1) as usize] {
+                    while self.note_count > 0 {
+
+                        if self.active_notes[
+                            self.note_log[
+                                (self.note_count - 1)
+                                as usize]
+                            as usize] {
+
+                            for (v, vd) in voice_data_zip!(self) {
+                                if vd.gate {
+                                    v.note_slide(
+                                        vd,
+                                        &mut self.params,
+                                        self.dev_params.slide,
+                                        self.note_log[
+                                            (self.note_count - 1)
+                                            as usize]);
+                                }
+                            }
+                            break;
+                        }
+
+                        self.note_count -= 1;
+                    }
+
+                    if self.note_count == 0 {
+                        self.mono_active = false;
+                        for an in self.active_notes.iter_mut() {
+                            *an = false;
+                        }
+
+                        for (v, vd) in voice_data_zip!(self) {
+                            if vd.gate {
+                                v.note_off(vd, &mut self.params);
+                            }
+                        }
+                    }
+                }
+            },
+        }
+    }
+
+    // Returns the index of a free voice for a new note. If the
+    // polyphony limit is reached, a voice is stolen according to
+    // the steal mode. Returns None if the note should not be played.
+    fn alloc_voice(&mut self, note: i32) -> Option<usize> {
+        let active =
+            self.voice_data.iter()
+                .filter(|vd| vd.is_on && vd.fade_left == 0)
+                .count();
+
+        if active >= self.dev_params.max_voices {
+            let victim = self.find_steal_victim(note)?;
+            self.steal_voice(victim);
+        }
+
+        let free = self.voice_data.iter().position(|vd| !vd.is_on);
+        if free.is_some() {
+            return free;
+        }
+
+        // All voices are busy, even with fading out voices.
+        // Reuse the victim directly without fade out then:
+        let victim = self.find_steal_victim(note)?;
+        self.voice_data[victim].is_on = false;
+        Some(victim)
+    }
+
+    fn find_steal_victim(&self, note: i32) -> Option<usize> {
+        let has_released =
+            self.voice_data.iter()
+                .any(|vd| vd.is_on && vd.fade_left == 0 && !vd.gate);
+
+        // Prefer voices that are already in their release stage. The
+        // unisono voices the current note on just started are never
+        // stolen, a hit would replace its own voices otherwise:
+        let candidates =
+            self.voice_data.iter().enumerate()
+                .filter(|(_, vd)| vd.is_on && vd.fade_left == 0)
+                .filter(|(_, vd)| vd.note_id != self.note_id_count)
+                .filter(|(_, vd)| !has_released || !vd.gate);
+
+        let oldest =
+            candidates.clone().min_by_key(|(_, vd)| vd.started);
+
+        let victim =
+            match self.dev_params.steal_mode {
+                StealMode::Oldest => oldest,
+                StealMode::Quietest => {
+                    candidates.min_by(|(ia, a), (ib, b)| {
+                        let la = self.voices[*ia].level(a, &self.params);
+                        let lb = self.voices[*ib].level(b, &self.params);
+                        la.partial_cmp(&lb)
+                          .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                },
+                StealMode::SameNote => {
+                    candidates
+                        .filter(|(_, vd)| vd.note == note)
+                        .min_by_key(|(_, vd)| vd.started)
+                        .or(oldest)
+                },
+                StealMode::LowNotePriority => {
+                    candidates
+                        .max_by_key(|(_, vd)| vd.note)
+                        .filter(|(_, vd)| note < vd.note)
+                },
+                StealMode::HighNotePriority => {
+                    candidates
+                        .min_by_key(|(_, vd)| vd.note)
+                        .filter(|(_, vd)| note > vd.note)
+                },
+            };
+
+        victim.map(|(idx, _)| idx)
+    }
+
+    fn steal_voice(&mut self, idx: usize) {
+        let vd = &mut self.voice_data[idx];
+        if vd.gate {
+            self.voices[idx].note_off(vd, &mut self.params);
+        }
+        vd.fade_left =
+            ((self.sample_rate * STEAL_FADE_TIME) as i32).max(1);
+    }
+
+    fn release_all_notes(&mut self) {
         for (vd, v) in self.voice_data.iter_mut().zip(self.voices.iter_mut()) {
-            if vd.is_on { v.note_off(vd, &mut self.params); }
+            if vd.gate { v.note_off(vd, &mut self.params); }
         }
         self.mono_active = false;
         self.note_count = 0;
         for an in self.active_notes.iter_mut() {
             *an = false;
         }
+        for d in self.deferred_off.iter_mut() {
+            *d = false;
+        }
+    }
+
+    fn all_notes_off(&mut self)
+    {
+        self.release_all_notes();
         self.clear_events();
     }
 
-    // XXX: Invariant: note_on must only be called with increasing
-    //      delta_samples. Otherwise the algorithm in run() will
-    //      not behave well. The invariant is, that the self.events
-    //      array is sorted by ascending delta_samples.
-    pub fn note_on(&mut self, note: i32, velocity: i32, delta_samples: i32) {
-        for ev in self.events.iter_mut() {
-            if ev.typ == EventType::None {
-                ev.typ           = EventType::NoteOn;
-                ev.delta_samples = delta_samples;
-                ev.note          = note;
-                ev.velocity      = velocity;
-                break;
-            }
+    /// Queues an event `delta_samples` into the next call to `run()`.
+    /// Events may be pushed in any order. Returns false if the
+    /// event queue is full and the event had to be dropped, or if
+    /// the note or controller number is outside of 0..=127.
+    pub fn push_event(&mut self, typ: EventType, note: i32, velocity: i32,
+                      value: f32, delta_samples: i32) -> bool {
+        let has_note =
+            matches!(typ,
+                     EventType::NoteOn
+                     | EventType::NoteOff
+                     | EventType::PolyPressure
+                     | EventType::ControlChange);
+        if has_note && !(0..128).contains(&note) {
+            return false;
         }
+
+        self.events.push(Event { typ, delta_samples, note, velocity, value })
     }
 
-    pub fn note_off(&mut self, note: i32, delta_samples: i32) {
-        for ev in self.events.iter_mut() {
-            if ev.typ == EventType::None {
-                ev.typ           = EventType::NoteOff;
-                ev.delta_samples = delta_samples;
-                ev.note          = note;
-                break;
-            }
+    pub fn note_on(&mut self, note: i32, velocity: i32, delta_samples: i32) -> bool {
+        self.push_event(EventType::NoteOn, note, velocity, 0.0, delta_samples)
+    }
+
+    pub fn note_off(&mut self, note: i32, delta_samples: i32) -> bool {
+        self.push_event(EventType::NoteOff, note, 0, 0.0, delta_samples)
+    }
+
+    pub fn all_notes_off_at(&mut self, delta_samples: i32) -> bool {
+        self.push_event(EventType::AllNotesOff, 0, 0, 0.0, delta_samples)
+    }
+
+    /// Pitch bend from -1.0 to 1.0, scaled by the "pb_range" parameter.
+    pub fn pitch_bend(&mut self, bend: f32, delta_samples: i32) -> bool {
+        self.push_event(
+            EventType::PitchBend, 0, 0,
+            helpers::clamp(bend, -1.0, 1.0), delta_samples)
+    }
+
+    pub fn channel_pressure(&mut self, pressure: f32, delta_samples: i32) -> bool {
+        self.push_event(
+            EventType::ChannelPressure, 0, 0,
+            helpers::clamp(pressure, 0.0, 1.0), delta_samples)
+    }
+
+    pub fn poly_pressure(&mut self, note: i32, pressure: f32, delta_samples: i32) -> bool {
+        self.push_event(
+            EventType::PolyPressure, note, 0,
+            helpers::clamp(pressure, 0.0, 1.0), delta_samples)
+    }
+
+    /// Controller value from 0.0 to 1.0 for the MIDI controller `cc`.
+    pub fn control_change(&mut self, cc: u8, value: f32, delta_samples: i32) -> bool {
+        self.push_event(
+            EventType::ControlChange, cc as i32, 0,
+            helpers::clamp(value, 0.0, 1.0), delta_samples)
+    }
+
+    /// Maps the MIDI controller `cc` onto the parameter port `name`.
+    pub fn map_cc(&mut self, cc: u8, name: &str) -> bool {
+        self.params.as_mut().map_cc(cc, name)
+    }
+
+    /// Number of events that were dropped because the event queue was full.
+    pub fn dropped_events(&self) -> usize { self.events.dropped }
+
+    /// Number of voices that are playing, including fading out ones.
+    pub fn active_voices(&self) -> usize {
+        self.voice_data.iter().filter(|vd| vd.is_on).count()
+    }
+
+    /// Handles the note events of `wctr_signal_ops`. These carry no
+    /// velocity, the notes are played with the velocity of the
+    /// "vel" port instead.
+    pub fn op_event(&mut self, ev: &signals::Event) {
+        match ev {
+            signals::Event::NoteOn(n)  => {
+                self.note_on(*n as i32, self.dev_params.velocity, 0);
+            },
+            signals::Event::NoteOff(n) => { self.note_off(*n as i32, 0); },
         }
     }
 
+    /// Calculates the port values and updates the voice and device
+    /// parameters, to be called from `Op::exec()`.
+    pub fn exec_params(&mut self, regs: &[f32]) {
+        self.params.as_mut().calc_all(regs);
+        let dev_params = self.params.update();
+        self.set_dev_params(&dev_params);
+    }
+
+    pub fn set_dev_params(&mut self, dev_params: &SynthDeviceParams) {
+        self.set_voice_mode(dev_params.voice_mode);
+        self.dev_params = *dev_params;
+    }
+
     fn set_voice_mode(&mut self, vm: VoiceMode) {
-        if self.voice_mode == vm {
+        if self.dev_params.voice_mode == vm {
             return;
         }
 
         self.all_notes_off();
         for vd in self.voice_data.iter_mut() {
             vd.is_on = false;
+            vd.gate  = false;
         }
-        self.voice_mode = vm;
+        self.dev_params.voice_mode = vm;
     }
 
-    fn get_voice_mode(&self) -> VoiceMode { self.voice_mode }
+    fn get_voice_mode(&self) -> VoiceMode { self.dev_params.voice_mode }
 
     fn clear_events(&mut self) {
-        for e in self.events.iter_mut() { e.clear(); }
+        self.events.clear();
     }
 }
 
@@ -470,3 +1029,312 @@ impl<P, V: Voice<P>> SynthDevice<V, P> {
 //             does something. But copying the parameter data into
 //             the voices on each change is too wasteful too.
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ev(typ: EventType, note: i32, delta_samples: i32) -> Event {
+        Event { typ, delta_samples, note, velocity: 0, value: 0.0 }
+    }
+
+    #[test]
+    fn test_event_queue_order() {
+        let mut q = EventQueue::new();
+        q.push(ev(EventType::NoteOn,  1, 10));
+        q.push(ev(EventType::NoteOn,  2, 0));
+        q.push(ev(EventType::NoteOff, 3, 10));
+        q.push(ev(EventType::NoteOn,  4, 10));
+
+        assert_eq!(q.pop_due().unwrap().note, 2);
+        assert_eq!(q.pop_due(), None);
+        assert_eq!(q.next_delta(), Some(10));
+
+        q.advance(10);
+        assert_eq!(q.pop_due().unwrap().note, 3);
+        assert_eq!(q.pop_due().unwrap().note, 1);
+        assert_eq!(q.pop_due().unwrap().note, 4);
+        assert_eq!(q.next_delta(), None);
+    }
+
+    #[test]
+    fn test_event_queue_overflow() {
+        let mut q = EventQueue::new();
+        for i in 0..MAX_EVENTS {
+            assert!(q.push(ev(EventType::NoteOn, 0, i as i32)));
+        }
+        assert!(!q.push(ev(EventType::NoteOff, 0, 0)));
+        assert_eq!(q.dropped, 1);
+    }
+
+    struct TestParams {
+        params:     SignalIOParams,
+        dev_params: SynthDeviceParams,
+        gain:       f32,
+    }
+
+    impl AsMut<SignalIOParams> for TestParams {
+        fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+    }
+
+    impl VoiceParams for TestParams {
+        fn update(&mut self) -> SynthDeviceParams {
+            self.gain = self.params.values[0];
+            self.dev_params
+        }
+    }
+
+    // A voice that outputs the "gain" port on the left channel. Its
+    // level grows with each rendered sample, so that older voices
+    // are louder than freshly started ones.
+    #[derive(Clone)]
+    struct TestVoice {
+        level: f32,
+    }
+
+    impl Voice<TestParams> for TestVoice {
+        fn new(_sample_rate: f64) -> Self { TestVoice { level: 0.0 } }
+
+        fn note_on(&mut self, data: &mut VoiceData, _params: &mut TestParams,
+                   note: i32, velocity: i32, detune: f32, pan: f32) {
+            self.level = 0.0;
+            data.note_on(note, velocity, detune, pan);
+        }
+
+        fn note_off(&mut self, data: &mut VoiceData, _params: &mut TestParams) {
+            data.note_off();
+        }
+
+        fn note_slide(&mut self, data: &mut VoiceData, _params: &mut TestParams,
+                      slide: f32, note: i32) {
+            data.note_slide(slide, note);
+        }
+
+        fn get_note(&mut self, data: &mut VoiceData, _params: &mut TestParams) -> f64 {
+            data.get_note()
+        }
+
+        fn is_finished(&self, data: &VoiceData, _params: &TestParams) -> bool {
+            !data.gate
+        }
+
+        fn level(&self, _data: &VoiceData, _params: &TestParams) -> f32 {
+            self.level
+        }
+
+        fn run(&mut self, _data: &mut VoiceData, params: &mut TestParams,
+               _song_pos: f64, sample_num: usize,
+               out_offs: usize, outputs: &mut [f32]) {
+            for i in 0..sample_num {
+                outputs[(out_offs + i) * 2] += params.gain;
+            }
+            self.level += sample_num as f32;
+        }
+    }
+
+    fn test_device(unisono: i32, max_voices: usize, steal_mode: StealMode)
+        -> SynthDevice<TestVoice, TestParams> {
+
+        let mut dp = SynthDeviceParams::new();
+        dp.voices_unisono = unisono;
+        dp.max_voices     = max_voices;
+        dp.steal_mode     = steal_mode;
+
+        let mut p = SignalIOParams::new();
+        p.input("gain", 0.0, 1.0, 1.0);
+
+        let params = TestParams { params: p, dev_params: dp, gain: 1.0 };
+        let mut sd = SynthDevice::new(44100.0, params);
+        sd.set_dev_params(&dp);
+        sd
+    }
+
+    fn run_device(sd: &mut SynthDevice<TestVoice, TestParams>, len: usize) -> Vec<f32> {
+        let mut out = vec![0.0; len * 2];
+        sd.run(0.0, len, &mut [], &mut out);
+        out
+    }
+
+    fn mono_device() -> SynthDevice<TestVoice, TestParams> {
+        let mut sd = test_device(1, MAX_VOICES, StealMode::Oldest);
+        sd.params.dev_params.voice_mode = VoiceMode::MonoLegatoTrill;
+        let dp = sd.params.dev_params;
+        sd.set_dev_params(&dp);
+        sd
+    }
+
+    // Returns the sorted notes of the voices with an open gate.
+    fn gated_notes(sd: &SynthDevice<TestVoice, TestParams>) -> Vec<i32> {
+        let mut notes : Vec<i32> =
+            sd.voice_data.iter()
+                .filter(|vd| vd.is_on && vd.gate)
+                .map(|vd| vd.note)
+                .collect();
+        notes.sort();
+        notes
+    }
+
+    // Returns the sorted notes of the voices that play and are
+    // not fading out.
+    fn playing_notes(sd: &SynthDevice<TestVoice, TestParams>) -> Vec<i32> {
+        let mut notes : Vec<i32> =
+            sd.voice_data.iter()
+                .filter(|vd| vd.is_on && vd.fade_left == 0)
+                .map(|vd| vd.note)
+                .collect();
+        notes.sort();
+        notes
+    }
+
+    const STEAL_MODES : [StealMode; 5] = [
+        StealMode::Oldest,
+        StealMode::Quietest,
+        StealMode::SameNote,
+        StealMode::LowNotePriority,
+        StealMode::HighNotePriority,
+    ];
+
+    #[test]
+    fn test_unisono_above_polyphony() {
+        for mode in STEAL_MODES.iter() {
+            let mut sd = test_device(4, 2, *mode);
+            sd.note_on(60, 127, 0);
+            run_device(&mut sd, 64);
+
+            // The voices beyond the polyphony limit are dropped,
+            // the hit must not steal its own voices:
+            assert_eq!(playing_notes(&sd), vec![60, 60], "{:?}", mode);
+            assert!(sd.voice_data.iter().all(|vd| vd.fade_left == 0),
+                    "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_unisono_steals_older_notes() {
+        for mode in STEAL_MODES.iter() {
+            let mut sd = test_device(2, 3, *mode);
+            sd.note_on(60, 127, 0);
+            run_device(&mut sd, 64);
+            sd.note_on(64, 127, 0);
+            run_device(&mut sd, 1);
+
+            let expected =
+                match mode {
+                    // 64 is above the playing note and not played
+                    // with its second voice then:
+                    StealMode::LowNotePriority => vec![60, 60, 64],
+                    _                          => vec![60, 64, 64],
+                };
+            assert_eq!(playing_notes(&sd), expected, "{:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_mapped_cc() {
+        let mut sd = test_device(1, MAX_VOICES, StealMode::Oldest);
+        assert!(sd.map_cc(20, "gain"));
+        sd.note_on(60, 127, 0);
+        sd.control_change(20, 0.5, 4);
+
+        // The CC applies right at its sample:
+        let out = run_device(&mut sd, 8);
+        let left : Vec<f32> = out.iter().step_by(2).cloned().collect();
+        assert_eq!(left, vec![1.0, 1.0, 1.0, 1.0, 0.5, 0.5, 0.5, 0.5]);
+
+        // The controller value overrides the input without
+        // replacing it:
+        sd.exec_params(&[]);
+        assert_eq!(sd.params.gain, 0.5);
+        assert_eq!(sd.params.params.v(0), 1.0);
+
+        sd.params.params.unmap_cc(20);
+        sd.exec_params(&[]);
+        assert_eq!(sd.params.gain, 1.0);
+    }
+
+    #[test]
+    fn test_out_of_range_notes() {
+        let mut sd = test_device(1, MAX_VOICES, StealMode::Oldest);
+        assert!(!sd.note_on(128, 127, 0));
+        assert!(!sd.note_on(-1, 127, 0));
+        assert!(!sd.note_off(200, 0));
+        assert!(!sd.poly_pressure(128, 1.0, 0));
+        assert!(!sd.control_change(128, 1.0, 0));
+        assert!(sd.note_on(127, 127, 0));
+        assert!(sd.pitch_bend(1.0, 0));
+
+        run_device(&mut sd, 1);
+        assert_eq!(gated_notes(&sd), vec![127]);
+        assert_eq!(sd.dropped_events(), 0);
+    }
+
+    #[test]
+    fn test_sustain_poly() {
+        let mut sd = test_device(1, MAX_VOICES, StealMode::Oldest);
+        sd.note_on(60, 127, 0);
+        sd.note_on(64, 127, 0);
+        sd.control_change(CC_SUSTAIN as u8, 1.0, 1);
+        sd.note_off(60, 2);
+        run_device(&mut sd, 4);
+        assert_eq!(gated_notes(&sd), vec![60, 64]);
+
+        sd.control_change(CC_SUSTAIN as u8, 0.0, 0);
+        run_device(&mut sd, 1);
+        assert_eq!(gated_notes(&sd), vec![64]);
+    }
+
+    #[test]
+    fn test_sostenuto_poly() {
+        let mut sd = test_device(1, MAX_VOICES, StealMode::Oldest);
+        sd.note_on(60, 127, 0);
+        sd.control_change(CC_SOSTENUTO as u8, 1.0, 1);
+        // Only the keys down when the pedal was pressed are held:
+        sd.note_on(64, 127, 2);
+        sd.note_off(60, 3);
+        sd.note_off(64, 3);
+        run_device(&mut sd, 4);
+        assert_eq!(gated_notes(&sd), vec![60]);
+
+        sd.control_change(CC_SOSTENUTO as u8, 0.0, 0);
+        run_device(&mut sd, 1);
+        assert_eq!(gated_notes(&sd), vec![]);
+    }
+
+    #[test]
+    fn test_sustain_mono() {
+        let mut sd = mono_device();
+        sd.note_on(60, 127, 0);
+        sd.control_change(CC_SUSTAIN as u8, 1.0, 1);
+        sd.note_on(64, 127, 2);
+        sd.note_off(64, 3);
+        sd.note_off(60, 3);
+        run_device(&mut sd, 4);
+        assert_eq!(gated_notes(&sd).len(), 1);
+        assert_eq!(sd.voice_data.iter().find(|vd| vd.gate).unwrap()
+                     .destination_note, 64);
+
+        sd.control_change(CC_SUSTAIN as u8, 0.0, 0);
+        run_device(&mut sd, 1);
+        assert_eq!(gated_notes(&sd), vec![]);
+    }
+
+    #[test]
+    fn test_sostenuto_mono() {
+        let mut sd = mono_device();
+        sd.note_on(60, 127, 0);
+        sd.control_change(CC_SOSTENUTO as u8, 1.0, 1);
+        sd.note_on(64, 127, 2);
+        sd.note_off(64, 3);
+        sd.note_off(60, 3);
+        run_device(&mut sd, 4);
+
+        // 64 is released and slides back to the held 60:
+        assert_eq!(gated_notes(&sd).len(), 1);
+        assert_eq!(sd.voice_data.iter().find(|vd| vd.gate).unwrap()
+                     .destination_note, 60);
+
+        sd.control_change(CC_SOSTENUTO as u8, 0.0, 0);
+        run_device(&mut sd, 1);
+        assert_eq!(gated_notes(&sd), vec![]);
+    }
+}
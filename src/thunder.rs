@@ -0,0 +1,445 @@
+use crate::parameters::*;
+use crate::synth_device::*;
+use crate::state_variable_filter::*;
+use crate::envelope::*;
+use crate::slaughter::Oscillator;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+// Frequency ratios of the six square waves of the metallic hat
+// cluster, relative to the lowest one. Taken over from the TR-808.
+const HAT_RATIOS   : [f64; 6] = [1.0, 1.4827, 1.8003, 2.5460, 2.6303, 3.8967];
+const HAT_BASE_FREQ : f64     = 205.3;
+// Length of the noise click at the start of the kick in milliseconds.
+const KICK_CLICK_TIME : f32   = 3.0;
+// Release time in milliseconds of an open hat that was choked.
+const CHOKE_TIME      : f32   = 5.0;
+
+/// The drum sound that is played by a note, following the
+/// General MIDI percussion map.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ThunderDrum {
+    None,
+    Kick,
+    Snare,
+    ClosedHat,
+    OpenHat,
+}
+
+impl ThunderDrum {
+    pub fn from_note(note: i32) -> Self {
+        match note {
+            35 | 36      => ThunderDrum::Kick,
+            38 | 40      => ThunderDrum::Snare,
+            42 | 44      => ThunderDrum::ClosedHat,
+            46           => ThunderDrum::OpenHat,
+            _            => ThunderDrum::None,
+        }
+    }
+}
+
+pub struct ThunderParams {
+    dev_params:             SynthDeviceParams,
+    params:                 SignalIOParams,
+    hat_choke_gen:          u64,
+    kick_note:              f64,
+    kick_sweep:             f64,
+    kick_sweep_time:        f32,
+    kick_decay:             f32,
+    kick_click:             f32,
+    kick_level:             f32,
+    snare_note:             f64,
+    snare_tone:             f32,
+    snare_decay:            f32,
+    snare_noise_decay:      f32,
+    snare_filter_freq:      f32,
+    snare_level:            f32,
+    hat_tune:               f64,
+    hat_metal:              f32,
+    hat_closed_decay:       f32,
+    hat_open_decay:         f32,
+    hat_filter_freq:        f32,
+    hat_level:              f32,
+    vel_amp:                f32,
+}
+
+impl ThunderParams {
+    pub fn new() -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("k_tune",     0.0, 1.0, 0.5);
+        p.input("k_sweep",    0.0, 1.0, 0.5);
+        p.input("k_swt",      0.0, 1.0, 0.1);
+        p.input("k_dcy",      0.0, 1.0, 0.3);
+        p.input("k_click",    0.0, 1.0, 0.3);
+        p.input("k_lvl",      0.0, 1.0, 1.0);
+        p.input("s_tune",     0.0, 1.0, 0.5);
+        p.input("s_tone",     0.0, 1.0, 0.5);
+        p.input("s_dcy",      0.0, 1.0, 0.2);
+        p.input("s_ndcy",     0.0, 1.0, 0.25);
+        p.input("s_flt",      0.0, 1.0, 0.5);
+        p.input("s_lvl",      0.0, 1.0, 1.0);
+        p.input("h_tune",     0.0, 1.0, 0.5);
+        p.input("h_metal",    0.0, 1.0, 0.5);
+        p.input("h_cdcy",     0.0, 1.0, 0.1);
+        p.input("h_odcy",     0.0, 1.0, 0.3);
+        p.input("h_flt",      0.0, 1.0, 0.7);
+        p.input("h_lvl",      0.0, 1.0, 1.0);
+        p.input("vel_amp",    0.0, 1.0, 1.0);
+
+        let dev_params = SynthDeviceParams::new_with_params(&mut p);
+
+        let mut tp = ThunderParams {
+            dev_params,
+            hat_choke_gen:      0,
+            kick_note:          0.0,
+            kick_sweep:         0.0,
+            kick_sweep_time:    0.0,
+            kick_decay:         0.0,
+            kick_click:         0.0,
+            kick_level:         0.0,
+            snare_note:         0.0,
+            snare_tone:         0.0,
+            snare_decay:        0.0,
+            snare_noise_decay:  0.0,
+            snare_filter_freq:  0.0,
+            snare_level:        0.0,
+            hat_tune:           0.0,
+            hat_metal:          0.0,
+            hat_closed_decay:   0.0,
+            hat_open_decay:     0.0,
+            hat_filter_freq:    0.0,
+            hat_level:          0.0,
+            vel_amp:            0.0,
+
+            params:             p,
+        };
+        tp.update();
+        tp
+    }
+}
+
+impl AsMut<SignalIOParams> for ThunderParams {
+    fn as_mut(&mut self) -> &mut SignalIOParams { &mut self.params }
+}
+
+impl VoiceParams for ThunderParams {
+    fn update(&mut self) -> SynthDeviceParams {
+        let v = &self.params.values;
+
+        self.kick_note          = 24.0 + 24.0 * v[0] as f64;
+        self.kick_sweep         = 48.0 * v[1] as f64;
+        self.kick_sweep_time    = helpers::scalar_to_env_value(v[2]);
+        self.kick_decay         = helpers::scalar_to_env_value(v[3]);
+        self.kick_click         = v[4];
+        self.kick_level         = v[5];
+        self.snare_note         = 36.0 + 36.0 * v[6] as f64;
+        self.snare_tone         = v[7];
+        self.snare_decay        = helpers::scalar_to_env_value(v[8]);
+        self.snare_noise_decay  = helpers::scalar_to_env_value(v[9]);
+        self.snare_filter_freq  = helpers::param_to_frequency(v[10]);
+        self.snare_level        = v[11];
+        self.hat_tune           = helpers::pow(2.0, (v[12] as f64 - 0.5) * 2.0);
+        self.hat_metal          = v[13];
+        self.hat_closed_decay   = helpers::scalar_to_env_value(v[14]);
+        self.hat_open_decay     = helpers::scalar_to_env_value(v[15]);
+        self.hat_filter_freq    = helpers::param_to_frequency(v[16]);
+        self.hat_level          = v[17];
+        self.vel_amp            = v[18];
+
+        self.dev_params.update(&self.params);
+        self.dev_params
+    }
+}
+
+// Sets up an envelope as a one shot attack/decay envelope
+// without sustain. A zero attack starts right in the decay stage.
+fn trigger_decay(env: &mut Envelope, attack: f32, decay: f32) {
+    env.attack  = attack;
+    env.decay   = decay;
+    env.sustain = 0.0;
+    env.release = decay;
+    env.trigger();
+    if attack <= 0.0 {
+        env.next();
+    }
+}
+
+fn decay_finished(env: &Envelope) -> bool {
+    env.state == EnvelopeState::Sustain
+    || env.state == EnvelopeState::Finished
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ThunderVoice {
+    sample_rate: f64,
+    drum:       ThunderDrum,
+    phase:      f64,
+    hat_phases: [f64; 6],
+    choke_gen:  u64,
+    osc:        Oscillator,
+    filter:     Filter,
+    amp_env:    Envelope,
+    noise_env:  Envelope,
+    pitch_env:  Envelope,
+    rg:         helpers::RandGen,
+}
+
+impl ThunderVoice {
+    fn noise(&mut self) -> f32 {
+        (self.rg.next_open01() * 2.0 - 1.0) as f32
+    }
+
+    fn next_kick(&mut self, params: &ThunderParams, detune: f64) -> f32 {
+        let note =
+            params.kick_note + detune
+            + params.kick_sweep * self.pitch_env.get_value() as f64;
+        self.phase =
+            (self.phase + helpers::note_to_freq(note) / self.sample_rate).fract();
+
+        let tone  = helpers::fast_sin(self.phase * 2.0 * std::f64::consts::PI) as f32;
+        let click = self.noise() * self.noise_env.get_value() * params.kick_click;
+        self.pitch_env.next();
+        self.noise_env.next();
+
+        (tone + click) * self.amp_env.get_value()
+    }
+
+    fn next_snare(&mut self, params: &ThunderParams, detune: f64) -> f32 {
+        let tone  =
+            self.osc.next(params.snare_note + detune, 0.0, 0.5)
+            * self.amp_env.get_value();
+        let noise = self.noise();
+        let noise = self.filter.next(noise) * self.noise_env.get_value();
+        self.noise_env.next();
+
+        helpers::mix(noise, tone, params.snare_tone)
+    }
+
+    fn next_hat(&mut self, params: &ThunderParams, detune: f64) -> f32 {
+        let base  =
+            HAT_BASE_FREQ * params.hat_tune * helpers::pow(2.0, detune / 12.0)
+            / self.sample_rate;
+
+        let mut metal = 0.0;
+        for (phase, ratio) in self.hat_phases.iter_mut().zip(HAT_RATIOS.iter()) {
+            *phase = (*phase + base * ratio).fract();
+            metal += helpers::square_135(*phase * 2.0 * std::f64::consts::PI);
+        }
+        let metal = (metal / HAT_RATIOS.len() as f64) as f32;
+        let noise = self.noise();
+
+        self.filter.next(helpers::mix(noise, metal, params.hat_metal))
+        * self.amp_env.get_value()
+    }
+}
+
+impl Voice<ThunderParams> for ThunderVoice {
+    fn new(sample_rate: f64) -> Self {
+        let mut rg = helpers::RandGen::new_with_time();
+        ThunderVoice {
+            sample_rate,
+            drum:       ThunderDrum::None,
+            phase:      0.0,
+            hat_phases: [0.0; 6],
+            choke_gen:  0,
+            osc:        Oscillator::new(sample_rate, &mut rg),
+            filter:     Filter::new(sample_rate),
+            amp_env:    Envelope::new(sample_rate),
+            noise_env:  Envelope::new(sample_rate),
+            pitch_env:  Envelope::new(sample_rate),
+            rg,
+        }
+    }
+    fn note_on(&mut self, data: &mut VoiceData, params: &mut ThunderParams, note: i32, velocity: i32, detune: f32, pan: f32) {
+        data.note_on(note, velocity, detune, pan);
+
+        self.drum  = ThunderDrum::from_note(note);
+        self.phase = 0.0;
+
+        match self.drum {
+            ThunderDrum::None => {
+                self.amp_env.state = EnvelopeState::Finished;
+            },
+            ThunderDrum::Kick => {
+                trigger_decay(&mut self.amp_env,   1.0, params.kick_decay);
+                trigger_decay(&mut self.pitch_env, 0.0, params.kick_sweep_time);
+                trigger_decay(&mut self.noise_env, 0.0, KICK_CLICK_TIME);
+            },
+            ThunderDrum::Snare => {
+                trigger_decay(&mut self.amp_env,   1.0, params.snare_decay);
+                trigger_decay(&mut self.noise_env, 1.0, params.snare_noise_decay);
+                self.filter.set_type(FilterType::Bandpass);
+                self.filter.set_q(1.0);
+                self.filter.set_freq(params.snare_filter_freq);
+            },
+            ThunderDrum::ClosedHat | ThunderDrum::OpenHat => {
+                // Every hat chokes the open hats of earlier hits that
+                // are still ringing, but not the unisono voices of its
+                // own hit, which share the note id:
+                params.hat_choke_gen = data.note_id;
+                self.choke_gen       = data.note_id;
+
+                let decay =
+                    if self.drum == ThunderDrum::OpenHat {
+                        params.hat_open_decay
+                    } else {
+                        params.hat_closed_decay
+                    };
+                trigger_decay(&mut self.amp_env, 1.0, decay);
+                self.hat_phases = [0.0; 6];
+                self.filter.set_type(FilterType::Highpass);
+                self.filter.set_q(1.0);
+                self.filter.set_freq(params.hat_filter_freq);
+            },
+        }
+    }
+    fn note_off(&mut self, data: &mut VoiceData, _params: &mut ThunderParams) {
+        // One shot: the drum plays until its decay is finished.
+        data.note_off();
+    }
+    fn note_slide(&mut self, data: &mut VoiceData, _params: &mut ThunderParams, slide: f32, note: i32) {
+        data.note_slide(slide, note);
+    }
+    fn get_note(&mut self, data: &mut VoiceData, _params: &mut ThunderParams) -> f64 {
+        data.get_note()
+    }
+    fn is_finished(&self, _data: &VoiceData, _params: &ThunderParams) -> bool {
+        match self.drum {
+            ThunderDrum::Snare =>
+                decay_finished(&self.amp_env) && decay_finished(&self.noise_env),
+            _ => decay_finished(&self.amp_env),
+        }
+    }
+    fn level(&self, _data: &VoiceData, _params: &ThunderParams) -> f32 {
+        self.amp_env.get_value()
+    }
+    fn run(&mut self,
+           data: &mut VoiceData,
+           params: &mut ThunderParams,
+           _song_pos: f64,
+           sample_num: usize,
+           out_offs: usize,
+           outputs: &mut [f32]) {
+
+        if self.is_finished(data, params) {
+            return;
+        }
+
+        if self.drum == ThunderDrum::OpenHat
+           && params.hat_choke_gen != self.choke_gen
+           && self.amp_env.state != EnvelopeState::Release {
+
+            self.amp_env.release = CHOKE_TIME;
+            self.amp_env.off();
+        }
+
+        let level =
+            match self.drum {
+                ThunderDrum::Kick  => params.kick_level,
+                ThunderDrum::Snare => params.snare_level,
+                _                  => params.hat_level,
+            };
+        let amp       = helpers::volume_to_scalar(data.master_level) * level
+                        * (1.0 - params.vel_amp * (1.0 - data.velocity));
+        let pan_left  = helpers::pan_to_scalar_left(data.pan);
+        let pan_right = helpers::pan_to_scalar_right(data.pan);
+        let detune    = data.detune as f64;
+
+        for i in 0..sample_num {
+            let s =
+                match self.drum {
+                    ThunderDrum::Kick  => self.next_kick(params, detune),
+                    ThunderDrum::Snare => self.next_snare(params, detune),
+                    _                  => self.next_hat(params, detune),
+                } * amp;
+            outputs[(out_offs + i) * 2]     += s * pan_left;
+            outputs[(out_offs + i) * 2 + 1] += s * pan_right;
+
+            self.amp_env.next();
+        }
+    }
+}
+
+impl Op for SynthDevice<ThunderVoice, ThunderParams> {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.params.ports.clone(),
+            input_values:     self.params.params.inputs.clone(),
+            input_defaults:   self.params.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, ev: &Event) {
+        self.op_event(ev);
+    }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.exec_params(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let mut f : [f32; 1] = [0.0; 1];
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.run(0.0, num_samples, &mut f, buf);
+    }
+}
+
+pub fn new_thunder(sample_rate: f64) -> SynthDevice<ThunderVoice, ThunderParams> {
+    let params     = ThunderParams::new();
+    let dev_params = params.dev_params;
+    let mut sd : SynthDevice<ThunderVoice, ThunderParams> =
+        SynthDevice::new(sample_rate, params);
+    sd.set_dev_params(&dev_params);
+    sd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run_device(sd: &mut SynthDevice<ThunderVoice, ThunderParams>, len: usize) {
+        let mut out = vec![0.0; 128];
+        for _ in 0..(len / 64) {
+            sd.run(0.0, 64, &mut [], &mut out);
+        }
+    }
+
+    #[test]
+    fn test_hat_choke() {
+        let mut sd = new_thunder(44100.0);
+        sd.set_input("v_uniso", OpIn::Constant(helpers::unisono_to_param(2)), false);
+        sd.exec_params(&[]);
+
+        // The unisono voices of an open hat don't choke each other,
+        // and a snare does not choke the open hat:
+        sd.note_on(46, 127, 0);
+        sd.note_off(46, 1);
+        sd.note_on(38, 127, 64);
+        sd.note_off(38, 65);
+        run_device(&mut sd, 448);
+        assert_eq!(sd.active_voices(), 4);
+
+        // The closed hat chokes both open hat voices, the snare
+        // keeps ringing:
+        sd.note_on(42, 127, 0);
+        sd.note_off(42, 1);
+        run_device(&mut sd, 896);
+        assert_eq!(sd.active_voices(), 4);
+        assert_eq!(sd.params.hat_choke_gen, 3);
+    }
+}
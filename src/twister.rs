@@ -0,0 +1,206 @@
+use crate::parameters::*;
+use crate::all_pass_delay::AllPassDelay;
+use crate::delay_buffer::DelayBuffer;
+use crate::helpers::SignalIOParams;
+use crate::helpers;
+use wctr_signal_ops::signals::{OpIn, Op, OpIOSpec, Event};
+
+const NUM_STAGES    : usize = 6;
+const MAX_FEEDBACK  : f32   = 0.95;
+// Delay ranges in milliseconds of the flanger and chorus modes.
+const FLANGER_MIN   : f64   = 0.1;
+const FLANGER_RANGE : f64   = 5.0;
+const CHORUS_MIN    : f64   = 5.0;
+const CHORUS_RANGE  : f64   = 25.0;
+
+struct TwisterChannel {
+    stages:     Vec<AllPassDelay>,
+    delay:      DelayBuffer,
+    last:       f32,
+}
+
+impl TwisterChannel {
+    fn new(sample_rate: f64) -> Self {
+        let max_delay = (CHORUS_MIN + CHORUS_RANGE) * 0.001 * sample_rate;
+        TwisterChannel {
+            stages: (0..NUM_STAGES).map(|_| AllPassDelay::new()).collect(),
+            delay:  DelayBuffer::new(max_delay as usize + 2),
+            last:   0.0,
+        }
+    }
+
+    // `m` is the modulated position in the range 0..1.
+    fn next(&mut self, mode: TwisterMode, sample_rate: f64,
+            input: f32, m: f64, feedback: f32) -> f32 {
+
+        let input = input + self.last * feedback;
+
+        let out =
+            match mode {
+                TwisterMode::Phaser => {
+                    let freq = helpers::param_to_frequency(m as f32) as f64;
+                    let d    =
+                        (std::f64::consts::PI
+                         * freq.min(sample_rate * 0.49)
+                         / sample_rate).tan();
+
+                    let mut s = input;
+                    for st in self.stages.iter_mut() {
+                        st.delay(d as f32);
+                        s = st.update(s);
+                    }
+                    s
+                },
+                TwisterMode::Flanger | TwisterMode::Chorus => {
+                    let ms =
+                        if mode == TwisterMode::Flanger {
+                            FLANGER_MIN + FLANGER_RANGE * m
+                        } else {
+                            CHORUS_MIN + CHORUS_RANGE * m
+                        };
+
+                    self.delay.feed(input);
+                    self.delay.tap_linear(ms * 0.001 * sample_rate)
+                },
+            };
+
+        self.last = out;
+        out
+    }
+}
+
+/// Phaser, flanger and chorus effect. The phaser is a cascade of
+/// modulated first order all-pass stages, flanger and chorus are
+/// modulated short delays.
+pub struct Twister {
+    sample_rate:    f64,
+    params:         SignalIOParams,
+    mode:           TwisterMode,
+    amount:         f32,
+    feedback:       f32,
+    lfo_freq:       f64,
+    lfo_amount:     f32,
+    lfo_phase_offs: f64,
+    spread:         Spread,
+    dry:            f32,
+    wet:            f32,
+    lfo_phase:      f64,
+    left:           TwisterChannel,
+    right:          TwisterChannel,
+}
+
+impl Twister {
+    pub fn new(sample_rate: f64) -> Self {
+        let mut p = SignalIOParams::new();
+
+        p.input("mode",       0.0, 1.0, 0.0);
+        p.input("amount",     0.0, 1.0, 0.5);
+        p.input("fb",         0.0, 1.0, 0.0);
+        p.input("lfo_f",      0.0, 1.0, 0.3);
+        p.input("lfo_amt",    0.0, 1.0, 0.5);
+        p.input("lfo_phs",    0.0, 1.0, 0.0);
+        p.input("spread",     0.0, 1.0, 0.0);
+        p.input("dry",        0.0, 1.0, 1.0);
+        p.input("wet",        0.0, 1.0, 1.0);
+
+        Twister {
+            sample_rate,
+            mode:           p.v(0).into(),
+            amount:         p.v(1),
+            feedback:       p.v(2) * MAX_FEEDBACK,
+            lfo_freq:       helpers::param_to_lfo_freq(p.v(3)),
+            lfo_amount:     p.v(4),
+            lfo_phase_offs: p.v(5) as f64,
+            spread:         p.v(6).into(),
+            dry:            p.v(7),
+            wet:            p.v(8),
+            lfo_phase:      0.0,
+            left:           TwisterChannel::new(sample_rate),
+            right:          TwisterChannel::new(sample_rate),
+
+            params:         p,
+        }
+    }
+
+    fn modulation(&self, phase: f64) -> f64 {
+        let lfo =
+            helpers::fast_sin(phase.fract() * 2.0 * std::f64::consts::PI) as f32;
+        helpers::clamp(self.amount + self.lfo_amount * 0.5 * lfo, 0.0, 1.0) as f64
+    }
+
+    pub fn process(&mut self, buf: &mut [f32]) {
+        let right_offs =
+            self.lfo_phase_offs
+            + if self.spread == Spread::ModInvert { 0.5 } else { 0.0 };
+        let right_sign =
+            if self.spread == Spread::FullInvert { -1.0 } else { 1.0 };
+        let lfo_delta = self.lfo_freq / self.sample_rate;
+
+        for frame in buf.chunks_mut(2) {
+            if frame.len() < 2 { break; }
+
+            let m_left  = self.modulation(self.lfo_phase);
+            let m_right = self.modulation(self.lfo_phase + right_offs);
+
+            let left  =
+                self.left.next(
+                    self.mode, self.sample_rate, frame[0], m_left, self.feedback);
+            let right =
+                self.right.next(
+                    self.mode, self.sample_rate, frame[1], m_right, self.feedback);
+
+            frame[0] = frame[0] * self.dry + left  * self.wet;
+            frame[1] = frame[1] * self.dry + right * self.wet * right_sign;
+
+            self.lfo_phase = (self.lfo_phase + lfo_delta).fract();
+        }
+    }
+}
+
+impl Op for Twister {
+    fn io_spec(&self, index: usize) -> OpIOSpec {
+        OpIOSpec {
+            inputs:           self.params.ports.clone(),
+            input_values:     self.params.inputs.clone(),
+            input_defaults:   self.params.defaults.clone(),
+            outputs:          vec![],
+            output_regs:      vec![],
+            audio_out_groups: vec![],
+            index,
+        }
+    }
+
+    fn event(&mut self, _ev: &Event) { }
+
+    fn init_regs(&mut self, _start_reg: usize, _regs: &mut [f32]) { }
+
+    fn get_output_reg(&mut self, _name: &str) -> Option<usize> { None }
+
+    fn set_input(&mut self, name: &str, to: OpIn, as_default: bool) -> bool {
+        self.params.set(name, to, as_default)
+    }
+
+    fn exec(&mut self, _t: f32, regs: &mut [f32]) {
+        self.mode           = self.params.inputs[0].calc(regs).into();
+        self.amount         = self.params.inputs[1].calc(regs);
+        self.feedback       =
+            helpers::clamp(self.params.inputs[2].calc(regs), 0.0, 1.0) * MAX_FEEDBACK;
+        self.lfo_freq       =
+            helpers::param_to_lfo_freq(self.params.inputs[3].calc(regs));
+        self.lfo_amount     = self.params.inputs[4].calc(regs);
+        self.lfo_phase_offs = self.params.inputs[5].calc(regs) as f64;
+        self.spread         = self.params.inputs[6].calc(regs).into();
+        self.dry            = self.params.inputs[7].calc(regs);
+        self.wet            = self.params.inputs[8].calc(regs);
+    }
+
+    fn render(&mut self, num_samples: usize, offs: usize, input_idx: usize, bufs: &mut Vec<Vec<f32>>)
+    {
+        let buf = &mut bufs[input_idx][(offs * 2)..((offs + num_samples) * 2)];
+        self.process(buf);
+    }
+}
+
+pub fn new_twister(sample_rate: f64) -> Twister {
+    Twister::new(sample_rate)
+}